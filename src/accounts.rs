@@ -0,0 +1,160 @@
+use color_eyre::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::DefaultTerminal;
+use rusqlite::Connection;
+
+use crate::{db, install, signal, transport, types};
+
+// what the account picker shows next to the number
+pub struct AccountInfo {
+    pub name: Option<String>,
+    pub last_activity: Option<u64>,
+}
+
+impl AccountInfo {
+    pub fn label(&self, number: &str) -> String {
+        let mut label = number.to_string();
+
+        if let Some(name) = &self.name {
+            label.push_str(&format!("  {}", name));
+        }
+
+        label.push_str(&match self.last_activity {
+            Some(timestamp) => format!("  {}", crate::app::format_timestamp(timestamp, "%Y-%m-%d %H:%M")),
+            None => "  no messages".to_string(),
+        });
+
+        label
+    }
+}
+
+pub fn infos(
+    stdin: &mut transport::Writer,
+    stdout: &mut transport::Reader,
+    db: &Connection,
+    accounts: &[types::SignalAccount],
+) -> Vec<AccountInfo> {
+    accounts.iter()
+        .map(|account| AccountInfo {
+            name: profile_name(stdin, stdout, &account.number).or(db::own_name(db, &account.number).unwrap_or(None)),
+            last_activity: db::last_activity(db, &account.number).unwrap_or(None),
+        })
+        .collect()
+}
+
+// our own profile, listContacts includes it when asked for our number
+fn profile_name(stdin: &mut transport::Writer, stdout: &mut transport::Reader, number: &str) -> Option<String> {
+    let result = signal::call(stdin, stdout, "listContacts", serde_json::json!({
+        "account": number,
+        "recipient": [number],
+    })).ok()?;
+
+    let contacts: Vec<types::SignalContact> = serde_json::from_value(result).ok()?;
+    let profile = contacts.first()?.profile.as_ref()?;
+
+    let name = format!(
+        "{} {}",
+        profile.given_name.as_deref().unwrap_or_default(),
+        profile.family_name.as_deref().unwrap_or_default()
+    );
+
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Removal {
+    // unregister or unlink with signal, then remove
+    Unregister,
+    // only the signal-cli data on this computer
+    Local,
+    // keep the account, drop what signal-tui stored
+    Messages,
+}
+
+// asks how to remove the account and does it, true if anything changed
+pub fn remove(
+    terminal: &mut DefaultTerminal,
+    stdin: &mut transport::Writer,
+    stdout: &mut transport::Reader,
+    db: &Connection,
+    number: &str,
+) -> Result<bool> {
+    let message = format!(
+        "What should happen to {}?\n\n\
+        'u' - unregister it with Signal and remove it. A linked device gets unlinked,\n\
+        on the primary device people can not message the number anymore\n\
+        'r' - only remove it from this computer\n\
+        'p' - keep it, only delete the messages signal-tui stored for it\n\
+        esc - cancel",
+        number
+    );
+
+    let Some(removal) = ask(terminal, &message, |code| match code {
+        KeyCode::Char('u') => Some(Removal::Unregister),
+        KeyCode::Char('r') => Some(Removal::Local),
+        KeyCode::Char('p') => Some(Removal::Messages),
+        _ => None,
+    })? else {
+        return Ok(false);
+    };
+
+    let what = match removal {
+        Removal::Unregister => "unregister and remove",
+        Removal::Local => "remove",
+        Removal::Messages => "delete the stored messages of",
+    };
+    let confirm = format!("This can not be undone.\nReally {} {}?\n\n'y' - yes | esc - cancel", what, number);
+
+    if ask(terminal, &confirm, |code| (code == KeyCode::Char('y')).then_some(()))?.is_none() {
+        return Ok(false);
+    }
+
+    install::draw_message(terminal, "Remove Account", &format!("Removing {}...", number))?;
+
+    let result = match removal {
+        Removal::Unregister => signal::call(stdin, stdout, "unregister", serde_json::json!({ "account": number }))
+            .and_then(|_| delete_local_data(stdin, stdout, number)),
+        Removal::Local => delete_local_data(stdin, stdout, number),
+        Removal::Messages => Ok(()),
+    };
+
+    if let Err(err) = result {
+        install::show_error(terminal, "Removing the account failed", &err)?;
+        return Ok(false);
+    }
+
+    db::purge_account(db, number)?;
+
+    Ok(true)
+}
+
+// signal-cli refuses this for registered accounts unless told otherwise
+fn delete_local_data(stdin: &mut transport::Writer, stdout: &mut transport::Reader, number: &str) -> Result<(), String> {
+    signal::call(stdin, stdout, "deleteLocalAccountData", serde_json::json!({
+        "account": number,
+        "ignoreRegistered": true,
+    })).map(|_| ())
+}
+
+// shows the message until one of the keys choose answers, None on esc
+fn ask<T>(terminal: &mut DefaultTerminal, message: &str, choose: impl Fn(KeyCode) -> Option<T>) -> Result<Option<T>> {
+    loop {
+        install::draw_message(terminal, "Remove Account", message)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+
+        if key.code == KeyCode::Esc {
+            return Ok(None);
+        }
+
+        if let Some(choice) = choose(key.code) {
+            return Ok(Some(choice));
+        }
+    }
+}
@@ -1,320 +1,832 @@
-use std::{collections::HashMap, process::ChildStdout, sync::RwLock, thread};
+use std::{collections::{HashMap, HashSet}, thread};
 
-use crossterm::{event::{self, Event}};
-use color_eyre::Result;
-use directories::ProjectDirs;
+use crossterm::event::{self, DisableBracketedPaste, DisableFocusChange, EnableBracketedPaste, EnableFocusChange, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use color_eyre::{eyre::eyre, Result};
 use ratatui::{
-    layout::{Constraint, Direction, Layout, Position, Rect}, style::{Color, Style}, widgets::{Block, BorderType, Borders, Padding, Paragraph, Wrap}, DefaultTerminal
+    layout::{Constraint, Direction, Layout, Margin, Position, Rect}, style::{Color, Style}, text::{Line, Span, Text}, widgets::{Block, BorderType, Borders, Clear, Padding, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Wrap}, DefaultTerminal
 };
 use rusqlite::Connection;
 
-use crate::{signal, types};
+use crate::{commands::{self, Command}, config, db, export, fuzzy, ui, editor::Editor, keymap::{self, Action, Context, KeyResult}, theme, notify::{self, NotificationLevel}, signal, transport, types};
+
+const MAX_INPUT_ROWS: usize = 8;
+const DRAFT_MARKER: &str = " ✎";
+// below sidebar width + this the sidebar turns into an overlay
+const MIN_CHAT_WIDTH: u16 = 40;
+const SIDEBAR_STEP: u16 = 2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum PromptKind {
+    Search,
+    Command,
+}
+
+#[derive(Clone, PartialEq)]
+enum SidebarEntry {
+    GroupsHeader,
+    ContactsHeader,
+    // account index, then an index into the groups/contacts vecs returned by its sync
+    Group(usize, usize),
+    Contact(usize, usize),
+}
+
+// one linked account, they all receive at the same time
+struct Account {
+    number: String,
+    groups: Vec<types::SignalGroup>,
+    contacts: Vec<types::SignalContact>,
+    contact_colors: HashMap<String, Color>,
+    own_uuid: Option<String>,
+}
+
+// the devices popup of the current account
+struct DevicesView {
+    // None until listDevices answers
+    devices: Option<Vec<types::SignalDevice>>,
+    selected: usize,
+    // the listDevices request it is waiting for
+    request: String,
+    // removing takes a second press
+    confirm_remove: Option<u64>,
+}
+
+fn device_name(device: &types::SignalDevice) -> String {
+    device.name.clone().filter(|name| !name.is_empty()).unwrap_or(format!("Device {}", device.id))
+}
+
+// short enough to go after every conversation in the unified sidebar
+fn account_tag(number: &str) -> String {
+    let chars: Vec<char> = number.chars().collect();
+    format!("[{}]", chars[chars.len().saturating_sub(4)..].iter().collect::<String>())
+}
+
+// next index after current that matches, wrapping around
+fn find_match(len: usize, current: usize, forward: bool, matches: impl Fn(usize) -> bool) -> Option<usize> {
+    (1..=len)
+        .map(|offset| if forward {
+            (current + offset) % len
+        } else {
+            (current + len - offset % len) % len
+        })
+        .find(|&i| matches(i))
+}
+
+pub fn format_timestamp(timestamp: u64, format: &str) -> String {
+    match chrono::DateTime::from_timestamp_millis(timestamp as i64) {
+        Some(time) => time.with_timezone(&chrono::Local).format(format).to_string(),
+        None => String::new(),
+    }
+}
+
+pub fn contact_name(contact: &types::SignalContact) -> String {
+    let mut name: String = String::new();
+
+    if let Some(profile) = &contact.profile {
+        name = profile.given_name.clone().unwrap_or_default();
+    }
+
+    if name.is_empty() {
+        if !contact.name.is_empty() {
+            name = contact.name.clone();
+        } else if contact.given_name.is_some() {
+            name = contact.given_name.clone().unwrap_or_default();
+        } else if contact.number.is_some() {
+            name = contact.number.clone().unwrap_or_default();
+        } else {
+            name = "Unnamed".to_string();
+        }
+    }
+
+    name
+}
+
+// shown is the accounts in the sidebar, only the current one unless it is unified
+fn sidebar_entries(
+    accounts: &[Account],
+    shown: &[usize],
+    show_groups: bool,
+    show_contacts: bool,
+    stats: &[HashMap<String, types::ConversationStats>],
+    sort_by_recent: bool,
+) -> Vec<SidebarEntry> {
+    let last_message = |a: usize, id: &String| stats[a].get(id).map(|s| s.last_message).unwrap_or(0);
+
+    let mut entries = vec![SidebarEntry::GroupsHeader];
+
+    if show_groups {
+        let mut order: Vec<(usize, usize)> = shown.iter()
+            .flat_map(|&a| (0..accounts[a].groups.len()).map(move |i| (a, i)))
+            .collect();
+
+        if sort_by_recent {
+            order.sort_by_key(|&(a, i)| std::cmp::Reverse(last_message(a, &accounts[a].groups[i].id)));
+        }
+
+        entries.extend(order.into_iter().map(|(a, i)| SidebarEntry::Group(a, i)));
+    }
+
+    entries.push(SidebarEntry::ContactsHeader);
+
+    if show_contacts {
+        let mut order: Vec<(usize, usize)> = shown.iter()
+            .flat_map(|&a| (0..accounts[a].contacts.len()).map(move |i| (a, i)))
+            .collect();
+
+        if sort_by_recent {
+            order.sort_by_key(|&(a, i)| std::cmp::Reverse(last_message(a, &accounts[a].contacts[i].uuid)));
+        }
+
+        entries.extend(order.into_iter().map(|(a, i)| SidebarEntry::Contact(a, i)));
+    }
+
+    entries
+}
+
+// conversations matching the switcher query, best match first and the most recent first among equal ones
+fn switcher_matches(
+    query: &str,
+    accounts: &[Account],
+    shown: &[usize],
+    stats: &[HashMap<String, types::ConversationStats>],
+) -> Vec<SidebarEntry> {
+    let last_message = |a: usize, id: &String| stats[a].get(id).map(|s| s.last_message).unwrap_or(0);
+
+    let mut matches: Vec<(i64, u64, SidebarEntry)> = vec![];
+
+    for &a in shown {
+        for (i, group) in accounts[a].groups.iter().enumerate() {
+            if let Some(score) = fuzzy::best_score(query, [group.name.as_str()]) {
+                matches.push((score, last_message(a, &group.id), SidebarEntry::Group(a, i)));
+            }
+        }
+    }
+
+    for (a, i, contact) in shown.iter().flat_map(|&a| accounts[a].contacts.iter().enumerate().map(move |(i, contact)| (a, i, contact))) {
+        let display_name = contact_name(contact);
+        let full_name = format!(
+            "{} {}",
+            contact.given_name.as_deref().unwrap_or_default(),
+            contact.family_name.as_deref().unwrap_or_default()
+        );
+        let profile_name = contact.profile.as_ref().map(|profile| format!(
+            "{} {}",
+            profile.given_name.as_deref().unwrap_or_default(),
+            profile.family_name.as_deref().unwrap_or_default()
+        )).unwrap_or_default();
+
+        let names = [
+            display_name.as_str(),
+            contact.name.as_str(),
+            full_name.trim(),
+            profile_name.trim(),
+            contact.nick_name.as_deref().unwrap_or_default(),
+            contact.nick_given_name.as_deref().unwrap_or_default(),
+            contact.nick_family_name.as_deref().unwrap_or_default(),
+            contact.number.as_deref().unwrap_or_default(),
+            contact.username.as_deref().unwrap_or_default(),
+        ];
+
+        if let Some(score) = fuzzy::best_score(query, names) {
+            matches.push((score, last_message(a, &contact.uuid), SidebarEntry::Contact(a, i)));
+        }
+    }
+
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+    matches.into_iter().map(|(_, _, entry)| entry).collect()
+}
+
+// focus events, bracketed paste and the kitty keyboard protocol, on top of what ratatui::init does
+fn enable_terminal_modes(keyboard_enhancement: bool) -> std::io::Result<()> {
+    crossterm::execute!(std::io::stdout(), EnableFocusChange, EnableBracketedPaste)?;
+
+    if keyboard_enhancement {
+        crossterm::execute!(
+            std::io::stdout(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+    }
+
+    Ok(())
+}
+
+fn disable_terminal_modes(keyboard_enhancement: bool) -> std::io::Result<()> {
+    if keyboard_enhancement {
+        crossterm::execute!(std::io::stdout(), PopKeyboardEnhancementFlags)?;
+    }
+
+    crossterm::execute!(std::io::stdout(), DisableFocusChange, DisableBracketedPaste)
+}
+
+// turns the modes off again however app returns, so an error does not leave the shell with them
+struct TerminalModes(bool);
+
+impl Drop for TerminalModes {
+    fn drop(&mut self) {
+        let _ = disable_terminal_modes(self.0);
+    }
+}
+
+// runs a composer command that goes to signal-cli, returns the request id and the status line text
+fn run_command(
+    stdin: &mut transport::Writer,
+    command: Command,
+    dest_id: &str,
+    dest_type: usize,
+    db: &Connection,
+    account_number: &str,
+) -> (String, String) {
+    match command {
+        Command::Me(action) => {
+            let style = format!("0:{}:ITALIC", action.encode_utf16().count());
+            let id = signal::send_msg_with(
+                stdin,
+                action,
+                serde_json::json!({ "textStyle": [style] }),
+                dest_id.to_string(),
+                dest_type,
+                db,
+                account_number.to_string(),
+            );
+            (id, String::new())
+        }
+        Command::Attach { path, caption } => {
+            let id = signal::send_msg_with(
+                stdin,
+                caption,
+                serde_json::json!({ "attachments": [path.to_string_lossy()] }),
+                dest_id.to_string(),
+                dest_type,
+                db,
+                account_number.to_string(),
+            );
+            (id, format!("Sending {}", path.display()))
+        }
+        Command::Expire(seconds) => (
+            signal::set_expiration(stdin, dest_id, dest_type, seconds),
+            match seconds {
+                0 => "Disappearing messages off".to_string(),
+                _ => format!("Disappearing messages set to {}", commands::format_duration(seconds)),
+            },
+        ),
+        Command::Block => (signal::set_blocked(stdin, dest_id, dest_type, true), "Blocked".to_string()),
+        Command::Unblock => (signal::set_blocked(stdin, dest_id, dest_type, false), "Unblocked".to_string()),
+        Command::Nick(name) => (
+            signal::set_nickname(stdin, dest_id, &name),
+            format!("Nickname set to {}, shown after the next sync", name),
+        ),
+        Command::Topic(topic) => (
+            signal::update_group(stdin, dest_id, serde_json::json!({ "description": topic })),
+            "Group description updated".to_string(),
+        ),
+        Command::Rename(name) => (
+            signal::update_group(stdin, dest_id, serde_json::json!({ "name": name })),
+            format!("Group renamed to {}, shown after the next sync", name),
+        ),
+        Command::Leave => (signal::quit_group(stdin, dest_id), "Left the group".to_string()),
+        Command::Search(_) | Command::Help => unreachable!("handled in the ui"),
+    }
+}
 
 pub fn app(
     terminal: &mut DefaultTerminal,
-    stdin: &mut std::process::ChildStdin, 
-    stdout: std::sync::Arc<std::sync::Mutex<ChildStdout>>,
+    stdin: &mut transport::Writer,
+    stdout: std::sync::Arc<std::sync::Mutex<transport::Reader>>,
+    account_numbers: Vec<String>,
     account_number: String,
 ) -> Result<()> {
-    let path = ProjectDirs::from(
-        "dev", 
-        "cyteon", 
-        "signal-tui"
-    ).map(|proj_dirs| {
-        proj_dirs.data_local_dir().to_path_buf()
-    }).unwrap();
+    let config = config::get();
+    let theme = theme::get();
+    let path = config.data_dir();
     std::fs::create_dir_all(&path)?;
 
-    let db = Connection::open(path.join("data.db")).unwrap();
+    let db = db::open().unwrap();
 
-    terminal.draw(|f| {
-        let centered = {
-            let area = f.area();
-            let h_margin = (area.width - 20) / 2;
-            let v_margin = (area.height - 3) / 2;
+    // the reader thread keeps stdout once it starts, so every account is synced up front
+    let mut accounts: Vec<Account> = vec![];
 
-            Rect::new(
-                h_margin,
-                v_margin,
-                20,
-                3
-            )
-        };
+    for number in account_numbers {
+        terminal.draw(|f| {
+            let text = format!("Syncronizing {}...", number);
+            f.render_widget(text.as_str(), ui::centered_rect(f.area(), text.chars().count() as u16, 1));
+        })?;
+
+        stdin.account = Some(number.clone());
+        let (groups, contacts) = signal::sync(
+            stdin,
+            &mut stdout.lock().unwrap(),
+        ).map_err(|err| eyre!("Syncronizing {} failed: {}", number, err))?;
+
+        let contact_colors = contacts.iter()
+            .filter_map(|contact| Some((contact.uuid.clone(), theme::contact_color(contact.color.as_deref()?)?)))
+            .collect();
+        let own_uuid = db::own_uuid(&db, &number).unwrap();
 
-        f.render_widget("Syncronizing...", centered);
-    })?;
+        accounts.push(Account { number, groups, contacts, contact_colors, own_uuid });
+    }
 
-    let (groups, contacts) = signal::sync(
-        stdin,
-        &mut *stdout.lock().unwrap(),
-    );
+    // the account requests go to and whose conversation can be open, the only one shown unless the sidebar is unified
+    let mut current = accounts.iter().position(|account| account.number == account_number).unwrap_or(0);
+    stdin.account = Some(accounts[current].number.clone());
+    let mut unified = config.layout.unified_sidebar;
 
     let mut selected_index = 0;
+    // first sidebar row on screen, and how many fit, for paging
+    let mut sidebar_offset: usize = 0;
+    let mut sidebar_rows: usize = 1;
+    let mut sidebar_width = config.layout.sidebar_width;
+    // width next to the status line from the last draw, the sidebar cant be widened past it
+    let mut main_width: u16 = 0;
     let mut show_groups = true;
     let mut show_contacts = true;
+    let mut sort_by_recent = config.layout.sort_by_recent;
 
-    let mut group_index = 0;
-    let mut contact_index = 0;
-
-    let mut index_group_map: HashMap<usize, &types::SignalGroup> = HashMap::new();
-    let mut index_contact_map: HashMap<usize, &types::SignalContact> = HashMap::new();
+    let mut entries: Vec<SidebarEntry> = vec![];
 
     let mut location_selected: bool = false;
     let mut chatting = false;
 
-    let mut input_text = String::new();
+    let mut editor = Editor::new();
 
     // 0 = group, 1 = contact
     let mut selected_type: usize = 0;
+    // group id or contact uuid of the open conversation
+    let mut selected_id = String::new();
+
+    // last read timestamp from when the conversation was opened, used for the "New messages" divider
+    let mut divider_timestamp: Option<u64> = None;
 
     let mut message_index: usize = 0;
 
-    signal::subscribe_receive(stdin);
+    let notification_settings = &config.notifications;
+    let mut window_unread: Option<usize> = None;
+
+    // only skip notifications for the open conversation if we know the user is looking at it
+    let mut focused = true;
+    // without this most terminals send shift+enter as a plain enter
+    let keyboard_enhancement = crossterm::terminal::supports_keyboard_enhancement().unwrap_or(false);
+    enable_terminal_modes(keyboard_enhancement)?;
+    let _modes = TerminalModes(keyboard_enhancement);
+
+    // daemons push incoming messages on their own
+    if !config::get().transport.is_daemon() {
+        for account in &accounts {
+            signal::subscribe_receive(stdin, &account.number);
+        }
+    }
+
+    let (events_tx, events_rx) = std::sync::mpsc::channel::<types::ReaderEvent>();
 
     let stoud_clone = stdout.clone();
     thread::spawn({
         move || {
-            loop {
-                {
-                    let mut stdout: std::sync::MutexGuard<'_, ChildStdout> = stoud_clone.lock().unwrap();
-                    signal::read_events_countinously(&mut *stdout);
-                }
-            }
+            let mut stdout: std::sync::MutexGuard<'_, transport::Reader> = stoud_clone.lock().unwrap();
+            let reason = match signal::read_events_countinously(&mut stdout, &events_tx) {
+                Ok(()) => "signal-cli went away".to_string(),
+                Err(err) => format!("Reading from signal-cli failed: {}", err),
+            };
+            let _ = events_tx.send(types::ReaderEvent::Closed(reason));
         }
     });
 
-    // (source name, message), cant be bothered to impl a better way rn :sob:
-    let mut messages: Vec<(String, String, String)> = vec![];
+    let mut messages: Vec<types::StoredMessage> = vec![];
 
     let mut scroll_offset: usize = 0;
 
+    let keymap = keymap::get();
+    let mut pending_keys: keymap::KeySequence = vec![];
+    let mut prompt: Option<(PromptKind, String)> = None;
+    let mut last_search = String::new();
+    let mut show_help = false;
+    // query and selected row of the ctrl-k popup
+    let mut switcher: Option<(String, usize)> = None;
+    let mut status: Option<String> = None;
+    // request id -> the command that sent it, so failures can say what failed
+    let mut request_labels: HashMap<String, String> = HashMap::new();
+    let mut devices: Option<DevicesView> = None;
+    // add, remove and rename requests, the device list is fetched again once they are done
+    let mut device_updates: HashSet<String> = HashSet::new();
+
+    // indexed like accounts, read again only when refresh is set
+    let mut stats: Vec<HashMap<String, types::ConversationStats>> = vec![];
+    let mut levels: Vec<HashMap<String, NotificationLevel>> = vec![];
+    let mut drafts: Vec<HashMap<String, String>> = vec![];
+    // set when signal-cli sent something or the user did anything, nothing changes the database otherwise
+    let mut refresh = true;
+
     loop {
-        terminal.draw(|f| {
-            let h_chunks = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints([
-                    Constraint::Length(30),
-                    Constraint::Fill(1)
-                ].as_ref())
-                .split(f.area());
-
-            let contacts_block = Block::default()
-                .borders(Borders::ALL).border_type(BorderType::Rounded);
-
-            let esc_action = if chatting {
-                "unfocus input"
-            } else if location_selected {
-                "back"
-            } else {
-                "exit"
+        let account_number = accounts[current].number.clone();
+        let shown: Vec<usize> = if unified { (0..accounts.len()).collect() } else { vec![current] };
+
+        // only the user changes these, a new message doesnt
+        if refresh {
+            levels = accounts.iter().map(|account| db::notification_levels(&db, &account.number).unwrap()).collect();
+        }
+
+        let level_of = |a: usize, conversation_id: &String, is_group: bool| {
+            levels[a].get(conversation_id).copied().unwrap_or(
+                if is_group && notification_settings.mentions_only_groups {
+                    NotificationLevel::MentionsOnly
+                } else {
+                    NotificationLevel::All
+                }
+            )
+        };
+
+        // notifications are sent from here rather than the reader thread so they dont end up in the middle of a draw
+        while let Ok(event) = events_rx.try_recv() {
+            refresh = true;
+
+            let incoming = match event {
+                types::ReaderEvent::Message(incoming) => incoming,
+                types::ReaderEvent::Error { id, message } => {
+                    if let Some(view) = devices.as_mut()
+                        && id.as_ref() == Some(&view.request) {
+                        view.devices = Some(vec![]);
+                    }
+
+                    status = Some(match id.and_then(|id| request_labels.remove(&id)) {
+                        Some(label) => format!("{} failed: {}", label, message),
+                        None => format!("signal-cli: {}", message),
+                    });
+                    continue;
+                }
+                types::ReaderEvent::Response { id, result } => {
+                    request_labels.remove(&id);
+
+                    if let Some(view) = devices.as_mut() {
+                        if id == view.request {
+                            let list: Vec<types::SignalDevice> = serde_json::from_value(result).unwrap_or_default();
+                            view.selected = view.selected.min(list.len().saturating_sub(1));
+                            view.devices = Some(list);
+                        } else if device_updates.remove(&id) {
+                            view.request = signal::list_devices(stdin);
+                            request_labels.insert(view.request.clone(), "Listing devices".to_string());
+                        }
+                    }
+                    continue;
+                }
+                types::ReaderEvent::Closed(reason) => {
+                    status = Some(format!("{}, restart signal-tui to get new messages", reason));
+                    continue;
+                }
+                types::ReaderEvent::Changed => continue,
             };
-            
-            let last_actions = if chatting {
-                "'enter' - send message"
-            } else if location_selected {
-                "up/down - navigate | 'e' - focus input"
-            } else if !location_selected {
-                "'enter' - select"
-            } else {
-                ""
+
+            let Some(a) = accounts.iter().position(|account| account.number == incoming.account_number) else {
+                continue;
             };
-            
-            let chat_block = Block::default()
-                .borders(Borders::ALL).border_type(BorderType::Rounded)
-                .title(format!(" 'esc' - {} | {} ", esc_action, last_actions))
-                .title_alignment(ratatui::layout::Alignment::Center)
-                .padding(Padding::horizontal(1));
+            let account = &accounts[a];
 
-            f.render_widget(contacts_block.clone(), h_chunks[0]);
-            f.render_widget(chat_block.clone(), h_chunks[1]);
+            if focused && location_selected && a == current && incoming.conversation_id == selected_id {
+                continue;
+            }
 
-            let contacts_layout = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints(vec![
-                    Constraint::Length(1); groups.len() + contacts.len() + 2 // two extra for "Groups" and "People"
-                ])
-                .split(contacts_block.inner(h_chunks[0]));
+            let level = level_of(a, &incoming.conversation_id, incoming.group_id.is_some());
 
-            let mut index = 0;
+            if !notify::should_notify(level, &incoming, &account.number, account.own_uuid.as_deref()) {
+                continue;
+            }
 
-            let group_title_style = if selected_index == index {
-                Style::default().bg(Color::Blue)
+            let mut title = match &incoming.group_id {
+                Some(group_id) => match account.groups.iter().find(|g| &g.id == group_id) {
+                    Some(group) => format!("{} in {}", incoming.source_name, group.name),
+                    None => incoming.source_name.clone(),
+                },
+                None => incoming.source_name.clone(),
+            };
+
+            if accounts.len() > 1 {
+                title = format!("{} {}", title, account_tag(&account.number));
+            }
+
+            notify::notify(notification_settings, &title, &incoming.message);
+        }
+
+        if refresh {
+            stats = accounts.iter().map(|account| db::conversation_stats(&db, &account.number).unwrap()).collect();
+            drafts = accounts.iter().map(|account| db::drafts(&db, &account.number).unwrap()).collect();
+        }
+
+        // save the draft whenever the text changed since the last time round
+        if location_selected && drafts[current].get(&selected_id).map(|d| d.as_str()).unwrap_or("") != editor.text() {
+            db::save_draft(&db, &account_number, &selected_id, editor.text()).unwrap();
+
+            if editor.text().is_empty() {
+                drafts[current].remove(&selected_id);
             } else {
-                Style::default()
+                drafts[current].insert(selected_id.clone(), editor.text().to_string());
+            }
+        }
+
+        if notification_settings.window_title {
+            let unread = stats.iter().enumerate()
+                .flat_map(|(a, stats)| stats.iter().map(move |(id, s)| (a, id, s)))
+                .filter(|(a, id, _)| level_of(*a, id, accounts[*a].groups.iter().any(|g| &g.id == *id)) != NotificationLevel::Muted)
+                .map(|(_, _, s)| s.unread)
+                .sum();
+
+            if window_unread != Some(unread) {
+                notify::set_window_title(unread);
+                window_unread = Some(unread);
+            }
+        }
+
+        // keep the selection on the same row even if sorting moved it
+        let previously_selected = entries.get(selected_index).cloned();
+        entries = sidebar_entries(&accounts, &shown, show_groups, show_contacts, &stats, sort_by_recent);
+
+        if let Some(previous) = previously_selected
+            && let Some(i) = entries.iter().position(|e| *e == previous) {
+            selected_index = i;
+        }
+        selected_index = selected_index.min(entries.len().saturating_sub(1));
+
+        if location_selected && refresh {
+            messages = db::messages(&db, &account_number, selected_type, &selected_id).unwrap();
+
+            if let Some(last) = messages.iter().map(|m| m.timestamp).max() {
+                db::mark_read(&db, &account_number, &selected_id, last).unwrap();
+            }
+        }
+
+        refresh = false;
+
+        terminal.draw(|f| {
+            if ui::too_small(f, ui::MIN_WIDTH, ui::MIN_HEIGHT) {
+                return;
+            }
+
+            let status_text = match &prompt {
+                Some((PromptKind::Search, text)) => Some(format!("/{}", text)),
+                Some((PromptKind::Command, text)) => Some(format!(":{}", text)),
+                None => match &status {
+                    Some(status) => Some(status.clone()),
+                    None if keymap.vim && chatting => Some("-- INSERT --".to_string()),
+                    None if keymap.vim => Some("-- NORMAL --".to_string()),
+                    None => None,
+                },
             };
 
-            if show_groups {
-                let title = Paragraph::new(
-                    format!(" ▼ Groups ({})", groups.len())
-                )
-                    .style(group_title_style);
+            let main_area = if let Some(status_text) = &status_text {
+                let v_chunks = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Fill(1), Constraint::Length(1)])
+                    .split(f.area());
 
-                f.render_widget(
-                    title, contacts_layout[0]
-                );
+                f.render_widget(Paragraph::new(status_text.as_str()).style(theme.system_message.style()), v_chunks[1]);
 
-                group_index = index;
-                index += 1;
-    
-                for group in groups.iter() {
-                    index_group_map.insert(index, group);
-
-                    let name: &String = &group.name;
-                    let layout = contacts_layout[index];
-    
-                    let style = if index == selected_index {
-                        Style::default().bg(Color::Blue)
-                    } else {
-                        Style::default()
-                    };
-                    
-                    let p = Paragraph::new(
-                        format!(" - {}", name)
-                    ).style(style);
-    
-                    f.render_widget(p, layout);
-                    
-                    index += 1;
+                if prompt.is_some() {
+                    f.set_cursor_position(Position::new(
+                        v_chunks[1].x + status_text.chars().count() as u16,
+                        v_chunks[1].y
+                    ));
                 }
+
+                v_chunks[0]
             } else {
-                let title = Paragraph::new(
-                    format!(" ► Groups ({})", groups.len())
-                )
-                    .style(group_title_style);
+                f.area()
+            };
 
-                f.render_widget(
-                    title, contacts_layout[index]
-                );
+            main_width = main_area.width;
+
+            // too narrow for both side by side, the open conversation gets the whole width
+            // and the sidebar is drawn over the empty chat otherwise
+            let narrow = main_area.width < sidebar_width + MIN_CHAT_WIDTH;
+
+            let (sidebar_rect, chat_rect) = if !narrow {
+                let h_chunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([
+                        Constraint::Length(sidebar_width),
+                        Constraint::Fill(1)
+                    ].as_ref())
+                    .split(main_area);
 
-                index += 1;
+                (h_chunks[0], h_chunks[1])
+            } else if location_selected {
+                (Rect::default(), main_area)
+            } else {
+                let width = sidebar_width.max(main_area.width * 2 / 3).min(main_area.width);
+                (Rect { width, ..main_area }, main_area)
+            };
+
+            let mut contacts_block = Block::default()
+                .borders(Borders::ALL).border_type(BorderType::Rounded)
+                .border_style(theme.borders.style());
+
+            if accounts.len() > 1 {
+                contacts_block = contacts_block.title(if unified {
+                    " All accounts ".to_string()
+                } else {
+                    format!(" {} ", account_number)
+                });
             }
 
-            let contact_title_style = if selected_index == index {
-                Style::default().bg(Color::Blue)
+            let context = if chatting {
+                Context::Input
+            } else if location_selected {
+                Context::Conversation
             } else {
-                Style::default()
+                Context::Sidebar
             };
 
-            if show_contacts {
-                let title = Paragraph::new(
-                    format!(" ▼ People ({})", contacts.len())
-                )
-                    .style(contact_title_style);
+            let hint = |action: Action, label: &str| {
+                keymap.key_for(context, action).map(|key| format!("'{}' - {}", key, label))
+            };
 
-                f.render_widget(
-                    title, contacts_layout[index]
-                );
-                
-                contact_index = index;
-                index += 1;
-    
-                for contact in contacts.iter() {
-                    index_contact_map.insert(index, contact);
-
-                    let mut name: String = String::new();
-    
-                    if let Some(profile) = &contact.profile {
-                        name = profile.given_name.clone().unwrap_or_default();
-                    }
-    
-                    if name.is_empty() {
-                        if !contact.name.is_empty() {
-                            name = contact.name.clone();
-                        } else if contact.given_name.is_some() {
-                            name = contact.given_name.clone().unwrap_or_default();
-                        } else if !contact.number.is_some() {
-                            name = contact.number.clone().unwrap_or_default();
-                        } else {
-                            name = "Unnamed".to_string();
-                        }
+            let hints: Vec<String> = match context {
+                Context::Input => vec![
+                    hint(Action::Back, "unfocus input"),
+                    hint(Action::Send, "send message"),
+                ],
+                Context::Conversation => vec![
+                    hint(Action::Back, "back"),
+                    hint(Action::FocusInput, "focus input"),
+                    hint(Action::Help, "help"),
+                ],
+                Context::Sidebar => vec![
+                    hint(Action::Back, "exit"),
+                    hint(Action::Select, "select"),
+                    hint(Action::ToggleSort, if sort_by_recent { "default order" } else { "sort by recent" }),
+                    hint(Action::CycleMute, "mute"),
+                    hint(Action::Switcher, "jump to"),
+                    if accounts.len() > 1 { hint(Action::SwitchAccount, "switch account") } else { None },
+                    hint(Action::Help, "help"),
+                ],
+            }.into_iter().flatten().collect();
+
+            let chat_block = Block::default()
+                .borders(Borders::ALL).border_type(BorderType::Rounded)
+                .border_style(theme.borders.style())
+                .title(format!(" {} ", hints.join(" | ")))
+                .title_alignment(ratatui::layout::Alignment::Center)
+                .padding(Padding::horizontal(1));
+
+            f.render_widget(chat_block.clone(), chat_rect);
+            f.render_widget(Clear, sidebar_rect);
+            f.render_widget(contacts_block.clone(), sidebar_rect);
+
+            // only the rows in view are drawn, the viewport follows the selection
+            let sidebar_area = contacts_block.inner(sidebar_rect);
+            sidebar_rows = (sidebar_area.height as usize).max(1);
+
+            if selected_index < sidebar_offset {
+                sidebar_offset = selected_index;
+            } else if selected_index >= sidebar_offset + sidebar_rows {
+                sidebar_offset = selected_index + 1 - sidebar_rows;
+            }
+            sidebar_offset = sidebar_offset.min(entries.len().saturating_sub(sidebar_rows));
+
+            let visible_entries = entries.iter().enumerate().skip(sidebar_offset).take(sidebar_area.height as usize);
+
+            let group_count: usize = shown.iter().map(|&a| accounts[a].groups.len()).sum();
+            let contact_count: usize = shown.iter().map(|&a| accounts[a].contacts.len()).sum();
+            let tag = |a: usize| if shown.len() > 1 { format!(" {}", account_tag(&accounts[a].number)) } else { String::new() };
+
+            for (row, (index, entry)) in visible_entries.enumerate() {
+                let (text, unread) = match entry {
+                    SidebarEntry::GroupsHeader => (
+                        format!(" {} Groups ({})", if show_groups { "▼" } else { "►" }, group_count),
+                        0
+                    ),
+                    SidebarEntry::ContactsHeader => (
+                        format!(" {} People ({})", if show_contacts { "▼" } else { "►" }, contact_count),
+                        0
+                    ),
+                    SidebarEntry::Group(a, i) => {
+                        let group = &accounts[*a].groups[*i];
+                        (
+                            format!(
+                                " - {}{}{}{}",
+                                group.name,
+                                tag(*a),
+                                level_of(*a, &group.id, true).label(),
+                                if drafts[*a].contains_key(&group.id) { DRAFT_MARKER } else { "" }
+                            ),
+                            stats[*a].get(&group.id).map(|s| s.unread).unwrap_or(0)
+                        )
                     }
-    
-                    let layout = contacts_layout[index];
-    
-                    let style = if index == selected_index {
-                        Style::default().bg(Color::Blue)
-                    } else {
-                        Style::default()
-                    };
-                    
-                    let p = Paragraph::new(
-                        format!(" - {}", name)
-                    ).style(style);
-    
-                    f.render_widget(p, layout);
-                    
-                    index += 1;
+                    SidebarEntry::Contact(a, i) => {
+                        let contact = &accounts[*a].contacts[*i];
+                        (
+                            format!(
+                                " - {}{}{}{}",
+                                contact_name(contact),
+                                tag(*a),
+                                level_of(*a, &contact.uuid, false).label(),
+                                if drafts[*a].contains_key(&contact.uuid) { DRAFT_MARKER } else { "" }
+                            ),
+                            stats[*a].get(&contact.uuid).map(|s| s.unread).unwrap_or(0)
+                        )
+                    }
+                };
+
+                let mut style = Style::default();
+
+                let text = if unread > 0 {
+                    style = style.patch(theme.unread.style());
+                    format!("{} ({})", text, unread)
+                } else {
+                    text
+                };
+
+                if index == selected_index {
+                    style = style.patch(theme.selection.style());
                 }
-            } else {
-                let title = Paragraph::new(
-                    format!(" ► People ({})", contacts.len())
-                )
-                    .style(contact_title_style);
 
-                f.render_widget(
-                    title, contacts_layout[index]
+                let row_area = Rect::new(sidebar_area.x, sidebar_area.y + row as u16, sidebar_area.width, 1);
+                f.render_widget(Paragraph::new(text).style(style), row_area);
+            }
+
+            if !sidebar_area.is_empty() && entries.len() > sidebar_rows {
+                // one position per possible offset, so the thumb reaches the end on the last page
+                let mut scrollbar_state = ScrollbarState::new(entries.len() - sidebar_rows + 1)
+                    .viewport_content_length(sidebar_rows)
+                    .position(sidebar_offset);
+
+                f.render_stateful_widget(
+                    Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                        .begin_symbol(None)
+                        .end_symbol(None)
+                        .style(theme.borders.style()),
+                    sidebar_rect.inner(Margin { vertical: 1, horizontal: 0 }),
+                    &mut scrollbar_state
                 );
             }
 
             if location_selected {
-                let messages_last_time = messages.len();
                 let was_at_bottom = message_index >= messages.len().saturating_sub(1);
-                messages = vec![];
 
-                if selected_type == 0 {
-                    let group_id = index_group_map.get(&selected_index).unwrap().id.clone();
+                let author = |m: &types::StoredMessage| {
+                    if m.source_number.is_empty() {
+                        m.source_name.clone()
+                    } else if m.source_number == account_number {
+                        "(you)".to_string()
+                    } else {
+                        m.source_name.clone()
+                    }
+                };
 
-                    let mut query = db.prepare("SELECT sourceName, sourceNumber, message FROM messages WHERE groupId = ? and pending = 0 and accountNumber = ?").unwrap();
-                    let mut rows = query.query(&[&group_id, &account_number]).unwrap();
+                let is_own = |m: &types::StoredMessage| m.source_uuid == "self" || m.source_number == account_number;
 
-                    while let Some(row) = rows.next().unwrap() {
-                        let source_name: String = row.get(0).unwrap();
-                        let source_number: String = row.get(1).unwrap_or_default();
-                        let message: String = row.get(2).unwrap();
+                let message_line = |m: &types::StoredMessage| {
+                    let mut spans = vec![];
 
-                        messages.push((source_name, source_number, message));
+                    if !config.timestamp_format.is_empty() {
+                        spans.push(Span::raw(format!("[{}] ", format_timestamp(m.timestamp, &config.timestamp_format))));
                     }
-                } else {
-                    let contact_uuid = index_contact_map.get(&selected_index).unwrap().uuid.clone();
-
-                    let mut query = db.prepare("SELECT sourceName, sourceNumber, message FROM messages WHERE (destinationUuid = ? OR (sourceUuid = ? AND destinationUuid = 'self')) and pending = 0 and accountNumber = ?").unwrap();
-                    let mut rows = query.query(&[&contact_uuid, &contact_uuid, &account_number]).unwrap();
 
-                    while let Some(row) = rows.next().unwrap() {
-                        let source_name: String = row.get(0).unwrap();
-                        let source_number: String = row.get(1).unwrap_or_default();
-                        let message: String = row.get(2).unwrap();
+                    let name_style = match accounts[current].contact_colors.get(&m.source_uuid) {
+                        Some(color) if theme.contact_colors && !is_own(m) => Style::default().fg(*color),
+                        _ => Style::default(),
+                    };
 
-                        messages.push((source_name, source_number, message));
-                    }
-                }
+                    spans.push(Span::styled(author(m), name_style));
+                    spans.push(Span::raw(format!(": {}", m.message)));
 
-                let chat_area = chat_block.inner(h_chunks[1]);
+                    Line::from(spans).style(if is_own(m) {
+                        theme.own_message.style()
+                    } else {
+                        theme.other_message.style()
+                    })
+                };
+
+                // first message from someone else that came in after the last read position
+                let divider_index = divider_timestamp.and_then(|last_read| {
+                    messages.iter().position(|m| {
+                        m.timestamp > last_read && m.source_uuid != "self" && m.source_number != account_number
+                    })
+                });
+
+                let chat_area = chat_block.inner(chat_rect);
                 let chat_height = chat_area.height as usize;
                 let chat_width = chat_area.width as usize;
 
+                // the input grows with the text up to a limit, then scrolls to keep the cursor in view
+                let (input_rows, input_cursor) = editor.layout(chat_width.saturating_sub(2));
+                let visible_input_rows = input_rows.len().clamp(1, MAX_INPUT_ROWS);
+                let first_input_row = input_cursor.0.saturating_sub(visible_input_rows - 1);
+                let input_height = visible_input_rows + 2;
+
                 let mut message_line_counts = Vec::with_capacity(messages.len());
-                for (source_name, source_number, message) in &messages {
-                    let author = if source_number.is_empty() {
-                        source_name.clone()
-                    } else if source_number == &account_number {
-                        "(you)".to_string()
-                    } else {
-                        source_name.clone()
-                    };
-                    let text = format!("{}: {}", author, message);
+                for (i, message) in messages.iter().enumerate() {
+                    let text = message_line(message).to_string();
 
-                    let line_count = text
+                    let mut line_count = text
                         .chars()
                         .collect::<Vec<_>>()
                         .chunks(chat_width.max(1))
                         .count()
                         .max(1);
-                    
+
+                    if divider_index == Some(i) {
+                        line_count += 1;
+                    }
+
                     message_line_counts.push(line_count);
                 }
 
-                let available_lines = chat_height.saturating_sub(3);
+                let available_lines = chat_height.saturating_sub(input_height);
                 let mut total_lines = 0;
                 let start = scroll_offset;
                 let mut end = scroll_offset;
@@ -339,15 +851,15 @@ pub fn app(
                 }
 
                 let mut c: Vec<Constraint> = Vec::new();
-                for i in start..end {
-                    c.push(Constraint::Length(message_line_counts[i] as u16));
+                for line_count in &message_line_counts[start..end] {
+                    c.push(Constraint::Length(*line_count as u16));
                 }
 
                 // have 1 extra for "no messages" label
-                if messages.len() == 0 {
+                if messages.is_empty() {
                     c.push(Constraint::Length(1));
                 }
-                c.push(Constraint::Length(3));
+                c.push(Constraint::Length(input_height as u16));
 
                 let chat_layout = Layout::default()
                     .direction(Direction::Vertical)
@@ -355,22 +867,25 @@ pub fn app(
                     .split(chat_area);
 
                 let mut layout_idx = 0;
-                for (i, (source_name, source_number, message)) in messages.iter().enumerate().skip(start).take(visible_count) {
+                for (i, message) in messages.iter().enumerate().skip(start).take(visible_count) {
                     let style = if i == message_index {
-                        Style::default().bg(Color::Blue)
+                        theme.selection.style()
                     } else {
                         Style::default()
                     };
 
-                    let author = if source_number.is_empty() {
-                        source_name.clone()
-                    } else if source_number == &account_number {
-                        "(you)".to_string()
-                    } else {
-                        source_name.clone()
-                    };
+                    let mut lines = vec![];
+
+                    if divider_index == Some(i) {
+                        lines.push(
+                            Line::from(format!("{:─^1$}", " New messages ", chat_width))
+                                .style(theme.system_message.style())
+                        );
+                    }
 
-                    let p = Paragraph::new(format!("{}: {}", author, message))
+                    lines.push(message_line(message));
+
+                    let p = Paragraph::new(Text::from(lines))
                         .style(style)
                         .wrap(Wrap { trim: false });
 
@@ -378,11 +893,11 @@ pub fn app(
                     layout_idx += 1;
                 }
 
-                if messages.len() == 0 {
+                if messages.is_empty() {
                     let layout = chat_layout[0];
                     let p = Paragraph::new(
                         "No messages :("
-                    ).style(Style::default());
+                    ).style(theme.system_message.style());
                     f.render_widget(p, layout);
                     layout_idx += 1;
                 }
@@ -392,133 +907,734 @@ pub fn app(
                     scroll_offset = messages.len().saturating_sub(visible_count);
                 }
 
-                let input = Paragraph::new(input_text.clone())
-                    .block(Block::bordered().title("Input"))
+                let input_area = if messages.is_empty() {
+                    chat_layout[1] // 1 cause the no messages label
+                } else {
+                    chat_layout[layout_idx]
+                };
+
+                let input = Paragraph::new(
+                    input_rows[first_input_row..first_input_row + visible_input_rows].join("\n")
+                )
+                    .block(Block::bordered().title(match commands::hint(editor.text()) {
+                        Some(hint) => format!("Input - {}", hint),
+                        None => "Input".to_string(),
+                    }))
                     .style(match chatting {
-                        true => Style::default().fg(Color::Blue),
+                        true => theme.input_focused.style(),
                         false => Style::default()
                     });
 
-                f.render_widget(
-                    input,
-                    if messages.len() == 0 {
-                        chat_layout[1] // 1 cause the no messages label
-                    } else {
-                        chat_layout[layout_idx]
-                    }
-                );
+                f.render_widget(input, input_area);
 
                 if chatting {
                     f.set_cursor_position(Position::new(
-                        chat_layout[layout_idx].x + 1 + input_text.len() as u16,
-                        chat_layout[layout_idx].y + 1 + if messages.len() == 0 { 1 } else { 0 }
+                        input_area.x + 1 + input_cursor.1 as u16,
+                        input_area.y + 1 + (input_cursor.0 - first_input_row) as u16
                     ));
                 }
             }
+
+            if show_help {
+                let bindings = keymap.bindings(context);
+
+                let lines: Vec<Line> = bindings.iter()
+                    .map(|(sequence, action)| Line::from(vec![
+                        Span::styled(format!("{:<12}", keymap::display_sequence(sequence)), theme.unread.style()),
+                        Span::raw(format!("{:<16} {}", action.name(), action.description())),
+                    ]))
+                    .collect();
+
+                let popup = ui::centered_rect(f.area(), 80, lines.len() as u16 + 2);
+
+                let block = Block::default()
+                    .borders(Borders::ALL).border_type(BorderType::Rounded)
+                    .border_style(theme.borders.style())
+                    .title(format!(" Keys - {} (any key to close) ", context.name()))
+                    .title_alignment(ratatui::layout::Alignment::Center);
+
+                f.render_widget(Clear, popup);
+                f.render_widget(Paragraph::new(lines).block(block), popup);
+            }
+
+            if let Some((query, selected)) = &switcher {
+                let matches = switcher_matches(query, &accounts, &shown, &stats);
+
+                let area = f.area();
+                let width = area.width.min(60);
+                let height = area.height.min(16);
+                let popup = Rect::new(
+                    area.x + area.width.saturating_sub(width) / 2,
+                    area.y + area.height.saturating_sub(height) / 3,
+                    width,
+                    height
+                );
+
+                let block = Block::default()
+                    .borders(Borders::ALL).border_type(BorderType::Rounded)
+                    .border_style(theme.borders.style())
+                    .title(" Jump to ")
+                    .title_alignment(ratatui::layout::Alignment::Center);
+                let inner = block.inner(popup);
+
+                f.render_widget(Clear, popup);
+                f.render_widget(block, popup);
+
+                let rows = (inner.height as usize).saturating_sub(1);
+                let first = selected.saturating_sub(rows.saturating_sub(1));
+
+                let mut lines = vec![Line::from(format!("> {}", query))];
+
+                for (i, entry) in matches.iter().enumerate().skip(first).take(rows) {
+                    let (a, name, detail, id) = match entry {
+                        SidebarEntry::Group(a, g) => {
+                            let group = &accounts[*a].groups[*g];
+                            (*a, group.name.clone(), "group".to_string(), &group.id)
+                        }
+                        SidebarEntry::Contact(a, c) => {
+                            let contact = &accounts[*a].contacts[*c];
+                            (
+                                *a,
+                                contact_name(contact),
+                                contact.number.clone().or(contact.username.clone()).unwrap_or_default(),
+                                &contact.uuid
+                            )
+                        }
+                        _ => continue,
+                    };
+
+                    let unread = stats[a].get(id).map(|s| s.unread).unwrap_or(0);
+
+                    let mut spans = vec![Span::raw(format!(" {}{}", name, tag(a)))];
+                    if !detail.is_empty() {
+                        spans.push(Span::styled(format!("  {}", detail), theme.system_message.style()));
+                    }
+
+                    let mut style = Style::default();
+                    if unread > 0 {
+                        spans.push(Span::raw(format!(" ({})", unread)));
+                        style = style.patch(theme.unread.style());
+                    }
+                    if i == *selected {
+                        style = style.patch(theme.selection.style());
+                    }
+
+                    lines.push(Line::from(spans).style(style));
+                }
+
+                if matches.is_empty() {
+                    lines.push(Line::from(" No matches").style(theme.system_message.style()));
+                }
+
+                f.render_widget(Paragraph::new(lines), inner);
+                f.set_cursor_position(Position::new(inner.x + 2 + query.chars().count() as u16, inner.y));
+            }
+
+            if let Some(view) = &devices {
+                let mut lines = vec![];
+
+                match &view.devices {
+                    None => lines.push(Line::from(" Loading...").style(theme.system_message.style())),
+                    Some(list) => {
+                        for (i, device) in list.iter().enumerate() {
+                            let mut name = device_name(device);
+                            if device.id == 1 {
+                                name.push_str(" (primary)");
+                            }
+
+                            let style = if i == view.selected { theme.selection.style() } else { Style::default() };
+
+                            lines.push(Line::from(vec![
+                                Span::raw(format!(" {:<28}", name)),
+                                Span::styled(
+                                    format!(
+                                        " created {}  last seen {}",
+                                        format_timestamp(device.created_timestamp, "%Y-%m-%d"),
+                                        format_timestamp(device.last_seen_timestamp, "%Y-%m-%d")
+                                    ),
+                                    theme.system_message.style()
+                                ),
+                            ]).style(style));
+                        }
+                    }
+                }
+
+                lines.push(Line::from(""));
+                lines.push(Line::from(" 'a' - link a device | 'r' - rename this device | 'x' - remove | esc - close").style(theme.system_message.style()));
+
+                let popup = ui::centered_rect(f.area(), 80, lines.len() as u16 + 2);
+
+                let block = Block::default()
+                    .borders(Borders::ALL).border_type(BorderType::Rounded)
+                    .border_style(theme.borders.style())
+                    .title(format!(" Devices - {} ", account_number))
+                    .title_alignment(ratatui::layout::Alignment::Center);
+
+                f.render_widget(Clear, popup);
+                f.render_widget(Paragraph::new(lines).block(block), popup);
+            }
         })?;
 
-        if event::poll(std::time::Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
+        if event::poll(config.poll_interval())? {
+            let event = event::read()?;
+
+            match &event {
+                Event::FocusGained => focused = true,
+                Event::FocusLost => focused = false,
+                // ratatui would pick the new size up on the next draw anyway, this clears whatever the old size left behind
+                Event::Resize(_, _) => terminal.autoresize()?,
+                Event::Paste(text) if chatting && switcher.is_none() => editor.insert_str(&text.replace("\r\n", "\n").replace('\r', "\n")),
+                _ => {}
+            }
+
+            let Event::Key(key) = event else {
+                continue;
+            };
+
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            status = None;
+
+            let context = if chatting {
+                Context::Input
+            } else if location_selected {
+                Context::Conversation
+            } else {
+                Context::Sidebar
+            };
+
+            let action = if let Some(view) = devices.as_mut() {
+                let count = view.devices.as_ref().map(|list| list.len()).unwrap_or(0);
+                let confirm_remove = view.confirm_remove.take();
+                let device = view.devices.as_ref().and_then(|list| list.get(view.selected)).cloned();
+
                 match key.code {
-                    crossterm::event::KeyCode::Char('e') => {
-                        if location_selected && !chatting {
-                            chatting = true;
-                        } else if chatting {
-                            input_text.push('e');
+                    KeyCode::Esc | KeyCode::Char('q') => devices = None,
+                    KeyCode::Up | KeyCode::Char('k') => view.selected = view.selected.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') => view.selected = (view.selected + 1).min(count.saturating_sub(1)),
+                    KeyCode::Char('x') | KeyCode::Delete => match device {
+                        Some(device) if device.id == 1 => status = Some("The primary device can not be removed".to_string()),
+                        Some(device) if confirm_remove == Some(device.id) => {
+                            let id = signal::remove_device(stdin, device.id);
+                            request_labels.insert(id.clone(), "Removing the device".to_string());
+                            device_updates.insert(id);
+                            status = Some(format!("Removing {}", device_name(&device)));
                         }
+                        Some(device) => {
+                            view.confirm_remove = Some(device.id);
+                            status = Some(format!("Press x again to remove {}", device_name(&device)));
+                        }
+                        None => {}
                     },
+                    KeyCode::Char('r') => {
+                        let message = "The name the other devices of this account show for this one.";
+
+                        if let Some(name) = ui::prompt(terminal, "Rename This Device", message, false)?
+                            && !name.is_empty() {
+                            let id = signal::set_device_name(stdin, &name);
+                            request_labels.insert(id.clone(), "Renaming this device".to_string());
+                            device_updates.insert(id);
+                        }
+                    }
+                    KeyCode::Char('a') => {
+                        let message = "Paste the sgnl://linkdevice link of the new device.\nScan its QR code with any scanner app to get it.";
+
+                        if let Some(uri) = ui::prompt(terminal, "Link a Device", message, false)? {
+                            if uri.starts_with("sgnl://linkdevice") {
+                                let id = signal::add_device(stdin, &uri);
+                                request_labels.insert(id.clone(), "Linking the device".to_string());
+                                device_updates.insert(id);
+                                status = Some("Linking the device...".to_string());
+                            } else if !uri.is_empty() {
+                                status = Some("Not a device link, it starts with sgnl://linkdevice".to_string());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+
+                None
+            } else if let Some((query, selected)) = switcher.as_mut() {
+                let matches = switcher_matches(query, &accounts, &shown, &stats);
+                let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+                match key.code {
+                    KeyCode::Esc => {
+                        switcher = None;
+                        None
+                    }
+                    KeyCode::Enter => {
+                        let entry = matches.get(*selected).cloned();
+                        switcher = None;
+
+                        // open it the same way the sidebar does, with its section expanded so it has a row
+                        entry.map(|entry| {
+                            show_groups = true;
+                            show_contacts = true;
+                            entries = sidebar_entries(&accounts, &shown, show_groups, show_contacts, &stats, sort_by_recent);
+                            selected_index = entries.iter().position(|e| *e == entry).unwrap_or(0);
 
-                    crossterm::event::KeyCode::Esc => {
-                        if chatting {
-                            chatting = false;
-                        } else if location_selected {
                             location_selected = false;
                             chatting = false;
-                            message_index = 0;  
-                            input_text = String::new();
-                        } else {
-                            break;
-                        }
-                    },
+                            divider_timestamp = None;
 
-                    crossterm::event::KeyCode::Up => {
-                        if !chatting {
-                            if location_selected {
-                                if message_index > 0 {
-                                    message_index -= 1;
+                            Action::Select
+                        })
+                    }
+                    KeyCode::Up | KeyCode::BackTab => {
+                        *selected = selected.saturating_sub(1);
+                        None
+                    }
+                    KeyCode::Char('p') if ctrl => {
+                        *selected = selected.saturating_sub(1);
+                        None
+                    }
+                    KeyCode::Down | KeyCode::Tab => {
+                        *selected = (*selected + 1).min(matches.len().saturating_sub(1));
+                        None
+                    }
+                    KeyCode::Char('n') if ctrl => {
+                        *selected = (*selected + 1).min(matches.len().saturating_sub(1));
+                        None
+                    }
+                    KeyCode::Backspace => {
+                        query.pop();
+                        *selected = 0;
+                        None
+                    }
+                    KeyCode::Char(c) if !ctrl => {
+                        query.push(c);
+                        *selected = 0;
+                        None
+                    }
+                    _ => None,
+                }
+            } else if let Some((kind, text)) = prompt.as_mut() {
+                match key.code {
+                    KeyCode::Esc => {
+                        prompt = None;
+                        None
+                    }
+                    KeyCode::Enter => {
+                        let kind = *kind;
+                        let text = text.trim().to_string();
+                        prompt = None;
+
+                        match kind {
+                            PromptKind::Search if text.is_empty() => None,
+                            PromptKind::Search => {
+                                last_search = text;
+                                Some(Action::SearchNext)
+                            }
+                            PromptKind::Command => match text.as_str() {
+                                "" => None,
+                                "q" | "q!" => Some(Action::Quit),
+                                name => match Action::from_name(name) {
+                                    Some(action) => Some(action),
+                                    None => {
+                                        status = Some(format!("Unknown command: {}", name));
+                                        None
+                                    }
+                                },
+                            },
+                        }
+                    }
+                    KeyCode::Backspace => {
+                        if text.pop().is_none() {
+                            prompt = None;
+                        }
+                        None
+                    }
+                    KeyCode::Char(c) => {
+                        text.push(c);
+                        None
+                    }
+                    _ => None,
+                }
+            } else if show_help {
+                show_help = false;
+                None
+            } else {
+                match keymap.handle(context, &mut pending_keys, key) {
+                    KeyResult::Action(action) => Some(action),
+                    KeyResult::Pending => None,
+                    KeyResult::Unbound(keys) => {
+                        if chatting {
+                            for chord in keys {
+                                if let KeyCode::Char(c) = chord.code
+                                    && !chord.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+                                    editor.insert_char(c);
                                 }
-                            } else if selected_index > 0 {
-                                selected_index -= 1;
                             }
                         }
+                        None
                     }
+                }
+            };
 
-                    crossterm::event::KeyCode::Down => {
-                        if !chatting {
-                            if location_selected {
-                                if message_index < messages.len().saturating_sub(1) {
-                                    message_index += 1;
+            let Some(action) = action else {
+                continue;
+            };
+
+            refresh = true;
+
+            match action {
+                Action::Quit => break,
+
+                Action::Back => {
+                    if chatting {
+                        chatting = false;
+                    } else if location_selected {
+                        location_selected = false;
+                        chatting = false;
+                        message_index = 0;
+                        divider_timestamp = None;
+                        editor.clear();
+                    } else {
+                        break;
+                    }
+                }
+
+                Action::Up => {
+                    if location_selected {
+                        message_index = message_index.saturating_sub(1);
+                    } else {
+                        selected_index = selected_index.saturating_sub(1);
+                    }
+                }
+
+                Action::Down => {
+                    if location_selected {
+                        if message_index < messages.len().saturating_sub(1) {
+                            message_index += 1;
+                        }
+                    } else if selected_index < entries.len().saturating_sub(1) {
+                        selected_index += 1;
+                    }
+                }
+
+                Action::Top => {
+                    if location_selected {
+                        message_index = 0;
+                    } else {
+                        selected_index = 0;
+                    }
+                }
+
+                Action::Bottom => {
+                    if location_selected {
+                        message_index = messages.len().saturating_sub(1);
+                    } else {
+                        selected_index = entries.len().saturating_sub(1);
+                    }
+                }
+
+                Action::PageUp => {
+                    if !location_selected {
+                        selected_index = selected_index.saturating_sub(sidebar_rows);
+                    }
+                }
+
+                Action::PageDown => {
+                    if !location_selected {
+                        selected_index = (selected_index + sidebar_rows).min(entries.len().saturating_sub(1));
+                    }
+                }
+
+                Action::SidebarWider => {
+                    sidebar_width = (sidebar_width + SIDEBAR_STEP)
+                        .min(main_width.saturating_sub(MIN_CHAT_WIDTH))
+                        .max(config::MIN_SIDEBAR_WIDTH);
+                }
+
+                Action::SidebarNarrower => {
+                    sidebar_width = sidebar_width.saturating_sub(SIDEBAR_STEP).max(config::MIN_SIDEBAR_WIDTH);
+                }
+
+                Action::FocusInput => {
+                    if location_selected {
+                        chatting = true;
+                    }
+                }
+
+                Action::DeleteBackward => editor.delete_backward(),
+                Action::DeleteForward => editor.delete_forward(),
+                Action::DeleteWord => editor.delete_word_backward(),
+                Action::DeleteLine => editor.delete_to_line_start(),
+                Action::CursorLeft => editor.move_left(),
+                Action::CursorRight => editor.move_right(),
+                Action::WordLeft => editor.move_word_left(),
+                Action::WordRight => editor.move_word_right(),
+                Action::LineStart => editor.move_line_start(),
+                Action::LineEnd => editor.move_line_end(),
+                Action::Newline => editor.insert_newline(),
+
+                Action::Complete => {
+                    if let Some(text) = commands::complete(editor.text()) {
+                        editor.set_text(&text);
+                    }
+                }
+
+                Action::ExternalEditor => {
+                    if location_selected {
+                        // the highlighted message goes below the scissors line as context
+                        let quote = messages.get(message_index).map(|m| format!("{}: {}", m.source_name, m.message));
+
+                        disable_terminal_modes(keyboard_enhancement)?;
+                        crate::restore_terminal();
+
+                        let result = crate::editor::compose_external(editor.text(), quote.as_deref());
+
+                        crate::resume_terminal(terminal)?;
+                        enable_terminal_modes(keyboard_enhancement)?;
+
+                        match result {
+                            Ok(text) => {
+                                editor.set_text(&text);
+                                chatting = true;
+                            }
+                            Err(err) => status = Some(format!("Could not run editor: {}", err)),
+                        }
+                    }
+                }
+
+                // up/down move between lines first, and only go through history from the first/last line
+                Action::HistoryPrevious => {
+                    if !editor.move_up() {
+                        editor.history_previous();
+                    }
+                }
+
+                Action::HistoryNext => {
+                    if !editor.move_down() {
+                        editor.history_next();
+                    }
+                }
+
+                Action::Send => {
+                    if chatting && !editor.text().trim().is_empty() {
+                        let text = editor.text().to_string();
+
+                        match commands::parse_input(&text, selected_type == 0) {
+                            commands::Input::Message(message) => {
+                                signal::send_msg(
+                                    stdin,
+                                    message,
+                                    selected_id.clone(),
+                                    selected_type,
+                                    &db,
+                                    account_number.clone(),
+                                );
+                                chatting = false;
+                            }
+                            // keep the text so it can be fixed
+                            commands::Input::Command(Err(err)) => {
+                                status = Some(err);
+                                continue;
+                            }
+                            commands::Input::Command(Ok(Command::Search(query))) => {
+                                // newest first, like searching up from the bottom
+                                let lowercase = query.to_lowercase();
+                                match find_match(messages.len(), message_index, false, |i| {
+                                    messages[i].message.to_lowercase().contains(&lowercase)
+                                }) {
+                                    Some(i) => message_index = i,
+                                    None => status = Some(format!("Pattern not found: {}", query)),
+                                }
+
+                                last_search = query;
+                                chatting = false;
+                            }
+                            commands::Input::Command(Ok(Command::Help)) => {
+                                let names: Vec<String> = commands::COMMANDS.iter().map(|info| format!("/{}", info.name)).collect();
+                                status = Some(format!("{} - tab completes, // sends a message starting with /", names.join(" ")));
+                            }
+                            commands::Input::Command(Ok(command)) => {
+                                let label = text.split_whitespace().next().unwrap_or_default().to_string();
+                                let (id, text) = run_command(stdin, command, &selected_id, selected_type, &db, &account_number);
+
+                                request_labels.insert(id, label);
+                                if !text.is_empty() {
+                                    status = Some(text);
                                 }
-                            } else if selected_index < groups.len() + contacts.len() + 1 {
-                                selected_index += 1;
                             }
                         }
+
+                        editor.push_history(&text);
+                        editor.clear();
                     }
+                }
 
-                    crossterm::event::KeyCode::Enter => {
+                Action::ToggleSort => {
+                    sort_by_recent = !sort_by_recent;
+                }
+
+                Action::CycleMute => {
+                    let conversation = match entries.get(selected_index) {
+                        Some(SidebarEntry::Group(a, i)) => Some((*a, &accounts[*a].groups[*i].id, true)),
+                        Some(SidebarEntry::Contact(a, i)) => Some((*a, &accounts[*a].contacts[*i].uuid, false)),
+                        _ => None,
+                    };
+
+                    if let Some((a, conversation_id, is_group)) = conversation {
+                        let level = level_of(a, conversation_id, is_group).next(is_group);
+                        db::set_notification_level(&db, &accounts[a].number, conversation_id, level).unwrap();
+                    }
+                }
+
+                Action::Select => {
+                    if !location_selected {
                         messages = vec![];
 
-                        if selected_index == group_index {
-                            show_groups = !show_groups;
-                        } else if selected_index == contact_index {
-                            show_contacts = !show_contacts;
-                        } else if chatting {
-                            signal::send_msg(
-                                stdin,
-                                input_text.clone(),
-                                if selected_type == 0 {
-                                    index_group_map.get(&selected_index).unwrap().id.clone()
+                        match entries[selected_index] {
+                            SidebarEntry::GroupsHeader => show_groups = !show_groups,
+                            SidebarEntry::ContactsHeader => show_contacts = !show_contacts,
+                            SidebarEntry::Group(a, i) | SidebarEntry::Contact(a, i) => {
+                                (selected_type, selected_id) = match entries[selected_index] {
+                                    SidebarEntry::Group(..) => (0, accounts[a].groups[i].id.clone()),
+                                    _ => (1, accounts[a].contacts[i].uuid.clone()),
+                                };
+
+                                // in the unified sidebar this can be another account, replies go out from it
+                                current = a;
+                                stdin.account = Some(accounts[a].number.clone());
+
+                                let unread = stats[a].get(&selected_id).map(|s| s.unread).unwrap_or(0);
+                                divider_timestamp = if unread > 0 {
+                                    Some(db::last_read(&db, &accounts[a].number, &selected_id).unwrap())
                                 } else {
-                                    index_contact_map.get(&selected_index).unwrap().uuid.clone()
-                                },
-                                selected_type,
-                                &db,
-                                account_number.clone(),
-                            );
+                                    None
+                                };
 
-                            input_text = String::new();
-                            chatting = false;
-                        } else {
-                            location_selected = true;
-                            chatting = false;
-                            message_index = 0;
-                            scroll_offset = 0;
+                                editor.set_text(drafts[a].get(&selected_id).map(|d| d.as_str()).unwrap_or(""));
 
-                            selected_type = if selected_index < contact_index {
-                                0
-                            } else {
-                                1
-                            };
+                                location_selected = true;
+                                chatting = false;
+                                message_index = 0;
+                                scroll_offset = 0;
+                            }
                         }
                     }
+                }
 
-                    char => {
-                        if chatting {
-                            if char == crossterm::event::KeyCode::Backspace {
-                                if !input_text.is_empty() {
-                                    input_text.pop();
-                                }
-                            } else {
-                                if let crossterm::event::KeyCode::Char(c) = char {
-                                    input_text.push(c);
-                                }
-                            }
+                Action::Search => {
+                    prompt = Some((PromptKind::Search, String::new()));
+                }
+
+                Action::SearchNext | Action::SearchPrevious => {
+                    if last_search.is_empty() {
+                        status = Some("No previous search".to_string());
+                        continue;
+                    }
+
+                    let query = last_search.to_lowercase();
+                    let forward = action == Action::SearchNext;
+
+                    let found = if location_selected {
+                        find_match(messages.len(), message_index, forward, |i| {
+                            messages[i].message.to_lowercase().contains(&query)
+                        }).map(|i| message_index = i)
+                    } else {
+                        find_match(entries.len(), selected_index, forward, |i| {
+                            let name = match entries[i] {
+                                SidebarEntry::Group(a, g) => accounts[a].groups[g].name.clone(),
+                                SidebarEntry::Contact(a, c) => contact_name(&accounts[a].contacts[c]),
+                                _ => return false,
+                            };
+                            name.to_lowercase().contains(&query)
+                        }).map(|i| selected_index = i)
+                    };
+
+                    if found.is_none() {
+                        status = Some(format!("Pattern not found: {}", last_search));
+                    }
+                }
+
+                Action::CommandMode => {
+                    prompt = Some((PromptKind::Command, String::new()));
+                }
+
+                Action::Help => {
+                    show_help = true;
+                }
+
+                Action::Switcher => {
+                    switcher = Some((String::new(), 0));
+                }
+
+                Action::SwitchAccount => {
+                    if accounts.len() < 2 {
+                        status = Some("No other account linked".to_string());
+                        continue;
+                    }
+
+                    if location_selected {
+                        location_selected = false;
+                        chatting = false;
+                        message_index = 0;
+                        divider_timestamp = None;
+                        editor.clear();
+                    }
+
+                    current = (current + 1) % accounts.len();
+                    stdin.account = Some(accounts[current].number.clone());
+                    status = Some(format!("Switched to {}", accounts[current].number));
+
+                    // the unified sidebar keeps its rows, so jump to the first one of the account instead
+                    let first = if unified {
+                        entries.iter().position(|entry| matches!(entry, SidebarEntry::Group(a, _) | SidebarEntry::Contact(a, _) if *a == current))
+                    } else {
+                        None
+                    };
+                    selected_index = first.unwrap_or(0);
+                    sidebar_offset = 0;
+                }
+
+                Action::ToggleUnified => {
+                    unified = !unified;
+                }
+
+                Action::Devices => {
+                    let request = signal::list_devices(stdin);
+                    request_labels.insert(request.clone(), "Listing devices".to_string());
+
+                    devices = Some(DevicesView {
+                        devices: None,
+                        selected: 0,
+                        request,
+                        confirm_remove: None,
+                    });
+                }
+
+                Action::Export => {
+                    let account = &accounts[current];
+
+                    match export::conversations(&db, &account.number, &account.groups, &account.contacts) {
+                        Ok(conversations) => {
+                            // the open conversation, the whole account from the sidebar
+                            let single = location_selected.then(|| conversations.iter()
+                                .find(|conversation| conversation.kind == selected_type && conversation.id == selected_id));
+
+                            status = match single {
+                                Some(None) => Some("Nothing stored for this conversation yet".to_string()),
+                                Some(single) => export::interactive(terminal, &db, &account.number, &conversations, single)?,
+                                None => export::interactive(terminal, &db, &account.number, &conversations, None)?,
+                            };
                         }
+                        Err(err) => status = Some(format!("Export failed: {}", err)),
                     }
                 }
+
+                Action::Unbound => {}
             }
         }
     }
 
+    if notification_settings.window_title {
+        notify::set_window_title(0);
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
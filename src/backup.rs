@@ -0,0 +1,609 @@
+use std::{fs::File, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+
+use color_eyre::{eyre::eyre, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{config, db, install, transport};
+
+// of the archive layout, restores refuse newer ones
+const FORMAT: u32 = 1;
+
+// what goes in front of the encrypted tar.gz: magic, salt, pbkdf2 iterations, nonce. the gcm tag is at the very end
+const MAGIC: &[u8; 8] = b"STUIBAK1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + 4 + NONCE_LEN;
+const TAG_LEN: usize = 16;
+
+// stored last in the archive, every file is checked against it before anything gets replaced
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub format: u32,
+    // of signal-tui, the database layout follows it
+    pub app_version: String,
+    // the signal-cli its data was written by, only set if it is included
+    pub signal_cli_version: Option<String>,
+    pub created: i64,
+    pub database_encrypted: bool,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+// the things a backup can hold, by their path in the archive.
+// config.toml and the themes are taken on their own, the config dir can be the data dir as well (eg. on macos)
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Part {
+    Data,
+    Config,
+    Themes,
+    SignalCli,
+}
+
+impl Part {
+    const ALL: [Part; 4] = [Part::Data, Part::Config, Part::Themes, Part::SignalCli];
+
+    fn archive_path(self) -> &'static str {
+        match self {
+            Part::Data => "data/data.db",
+            Part::Config => "config/config.toml",
+            Part::Themes => "config/themes",
+            Part::SignalCli => "signal-cli",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Part::Data => "messages",
+            Part::Config => "config",
+            Part::Themes => "themes",
+            Part::SignalCli => "signal-cli data",
+        }
+    }
+
+    // where it lives on this machine
+    fn target(self) -> PathBuf {
+        match self {
+            Part::Data => db::path(),
+            Part::Config => config::config_path(),
+            Part::Themes => config::project_dirs().config_dir().join("themes"),
+            Part::SignalCli => config::get().signal_cli_data_dir(),
+        }
+    }
+
+    // the files to archive with their names in it, nothing if there is nothing here
+    fn files(self, found: &mut Vec<(String, PathBuf)>) -> Result<()> {
+        let target = self.target();
+
+        if target.is_file() {
+            found.push((self.archive_path().to_string(), target));
+        } else if target.is_dir() {
+            files_in(&target, self.archive_path(), found)?;
+        }
+
+        Ok(())
+    }
+}
+
+// every file under dir with its path relative to it, using / so archives work across platforms
+fn files_in(dir: &Path, relative: &str, found: &mut Vec<(String, PathBuf)>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let relative = if relative.is_empty() { name } else { format!("{}/{}", relative, name) };
+        let kind = entry.file_type()?;
+
+        if kind.is_dir() {
+            files_in(&entry.path(), &relative, found)?;
+        } else if kind.is_file() {
+            found.push((relative, entry.path()));
+        }
+    }
+
+    Ok(())
+}
+
+// hashes what goes into the archive while it is written, so every file is only read once
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+// writes an encrypted backup of data.db, the config and themes, and if asked for the signal-cli data
+pub fn create(path: &Path, passphrase: &str, include_signal_cli: bool) -> Result<Manifest> {
+    let mut files: Vec<(String, PathBuf)> = vec![];
+
+    let database = Part::Data.target();
+    if !database.is_file() {
+        return Err(eyre!("there is no {} to back up yet", database.display()));
+    }
+
+    Part::Config.files(&mut files)?;
+    Part::Themes.files(&mut files)?;
+
+    let signal_cli_version = if include_signal_cli {
+        let dir = Part::SignalCli.target();
+        if !dir.is_dir() {
+            return Err(eyre!("there is no signal-cli data in {}", dir.display()));
+        }
+
+        Part::SignalCli.files(&mut files)?;
+
+        Some(config::get().signal_cli.version.clone())
+    } else {
+        None
+    };
+
+    let mut manifest = Manifest {
+        format: FORMAT,
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        signal_cli_version,
+        created: chrono::Local::now().timestamp_millis(),
+        database_encrypted: db::is_encrypted(),
+        files: vec![],
+    };
+
+    // copying data.db as it is could tear it if signal-tui writes to it meanwhile
+    let snapshot = database.with_extension("db.snapshot");
+    let _ = std::fs::remove_file(&snapshot);
+    db::snapshot(&snapshot)
+        .map_err(|err| eyre!("Could not copy {}: {}", database.display(), err))?;
+    files.insert(0, (Part::Data.archive_path().to_string(), snapshot.clone()));
+
+    // written next to the target first so a failed backup never replaces a good one
+    let partial = path.with_extension("partial");
+
+    let result = (|| -> Result<()> {
+        let output = File::create(&partial)
+            .map_err(|err| eyre!("Could not create {}: {}", partial.display(), err))?;
+        let encrypter = cipher::Encrypter::new(output, passphrase)?;
+        let mut archive = tar::Builder::new(GzEncoder::new(encrypter, Compression::default()));
+
+        for (name, source) in &files {
+            let file = File::open(source)
+                .map_err(|err| eyre!("Could not read {}: {}", source.display(), err))?;
+            let metadata = file.metadata()?;
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(metadata.len());
+            header.set_mode(0o600);
+            header.set_mtime(metadata.modified().ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|time| time.as_secs())
+                .unwrap_or(0));
+
+            let mut reader = HashingReader { inner: file.take(metadata.len()), hasher: Sha256::new() };
+            archive.append_data(&mut header, name, &mut reader)?;
+
+            manifest.files.push(ManifestFile {
+                path: name.clone(),
+                size: metadata.len(),
+                sha256: reader.hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect(),
+            });
+        }
+
+        let json = serde_json::to_vec_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o600);
+        archive.append_data(&mut header, "manifest.json", json.as_slice())?;
+
+        archive.into_inner()?.finish()?.finish()?;
+        Ok(())
+    })();
+
+    let _ = std::fs::remove_file(&snapshot);
+
+    if let Err(err) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(err);
+    }
+
+    std::fs::rename(&partial, path)
+        .map_err(|err| eyre!("Could not write {}: {}", path.display(), err))?;
+
+    Ok(manifest)
+}
+
+pub struct Restored {
+    pub manifest: Manifest,
+    // what was there before, moved aside instead of deleted
+    pub moved: Vec<PathBuf>,
+    pub parts: Vec<&'static str>,
+}
+
+// unpacks into a staging dir, checks the tag, the manifest and the versions, and only then replaces anything.
+// force skips the version checks, not the integrity ones
+pub fn restore(path: &Path, passphrase: &str, force: bool) -> Result<Restored> {
+    let _lock = db::lock_exclusive()?
+        .ok_or_else(|| eyre!("signal-tui is running, close it before restoring"))?;
+
+    let staging = config::get().data_dir().join(format!("restore-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    std::fs::create_dir_all(&staging)?;
+
+    let result = unpack(path, passphrase, &staging)
+        .and_then(|()| check(&staging, force))
+        .and_then(|manifest| {
+            // a daemon keeps its files open and would write its own state back over the restored one
+            if staging.join(Part::SignalCli.archive_path()).exists() && config::get().transport.is_daemon() && transport::connect().is_ok() {
+                return Err(eyre!("the signal-cli daemon is running, stop it before restoring its data"));
+            }
+
+            install_parts(&staging, manifest)
+        });
+
+    let _ = std::fs::remove_dir_all(&staging);
+
+    result
+}
+
+fn unpack(path: &Path, passphrase: &str, staging: &Path) -> Result<()> {
+    let mut file = File::open(path)
+        .map_err(|err| eyre!("Could not open {}: {}", path.display(), err))?;
+    let length = file.metadata()?.len();
+
+    if length < (HEADER_LEN + TAG_LEN) as u64 {
+        return Err(eyre!("{} is not a signal-tui backup", path.display()));
+    }
+
+    let mut header = [0; HEADER_LEN];
+    file.read_exact(&mut header)?;
+    if &header[..MAGIC.len()] != MAGIC {
+        return Err(eyre!("{} is not a signal-tui backup", path.display()));
+    }
+
+    let mut tag = [0; TAG_LEN];
+    file.seek(SeekFrom::End(-(TAG_LEN as i64)))?;
+    file.read_exact(&mut tag)?;
+    file.seek(SeekFrom::Start(HEADER_LEN as u64))?;
+
+    let encrypted = file.take(length - (HEADER_LEN + TAG_LEN) as u64);
+    let decrypter = cipher::Decrypter::new(encrypted, &header, tag, passphrase)?;
+
+    let damaged = |err: io::Error| eyre!("Could not read the backup, the passphrase is wrong or the file is damaged ({})", err);
+
+    let mut archive = tar::Archive::new(GzDecoder::new(decrypter));
+    for entry in archive.entries().map_err(damaged)? {
+        let mut entry = entry.map_err(damaged)?;
+
+        // unpack_in refuses paths that would end up outside of staging
+        if !entry.unpack_in(staging).map_err(damaged)? {
+            return Err(eyre!("the backup contains a file outside of it: {}", entry.path()?.display()));
+        }
+    }
+
+    // the tag is only checked once everything was read, gzip stops before that at its own end
+    io::copy(&mut archive.into_inner().into_inner(), &mut io::sink()).map_err(damaged)?;
+
+    Ok(())
+}
+
+fn check(staging: &Path, force: bool) -> Result<Manifest> {
+    let manifest: Manifest = serde_json::from_slice(&std::fs::read(staging.join("manifest.json"))
+        .map_err(|_| eyre!("the backup has no manifest"))?)?;
+
+    if manifest.format > FORMAT {
+        return Err(eyre!("the backup was made by a newer signal-tui ({}), update to restore it", manifest.app_version));
+    }
+
+    let ours = env!("CARGO_PKG_VERSION");
+    if !force && install::parse_version(&manifest.app_version) > install::parse_version(ours) {
+        return Err(eyre!(
+            "the backup was made by signal-tui {}, this is {}. Its database may not work here, update or pass --force",
+            manifest.app_version, ours
+        ));
+    }
+
+    // signal-cli upgrades its data on start and older versions can not read it back
+    let configured = &config::get().signal_cli.version;
+    if let Some(version) = &manifest.signal_cli_version
+        && !force && install::parse_version(version) > install::parse_version(configured) {
+        return Err(eyre!(
+            "the signal-cli data is from signal-cli {}, but {} is configured. Set signal_cli.version or pass --force",
+            version, configured
+        ));
+    }
+
+    for file in &manifest.files {
+        let path = staging.join(&file.path);
+
+        if !path.is_file() || path.metadata()?.len() != file.size || install::sha256_file(&path)? != file.sha256 {
+            return Err(eyre!("{} in the backup does not match its manifest, nothing was restored", file.path));
+        }
+    }
+
+    Ok(manifest)
+}
+
+// moves a file or directory, falling back to copying when staging is on another filesystem
+fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        let mut found = vec![];
+        files_in(from, "", &mut found)?;
+
+        for (name, source) in found {
+            let target = to.join(&name);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|err| eyre!("Could not create {}: {}", parent.display(), err))?;
+            }
+            std::fs::copy(&source, &target)?;
+        }
+
+        std::fs::remove_dir_all(from)?;
+    } else {
+        std::fs::copy(from, to)?;
+        std::fs::remove_file(from)?;
+    }
+
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
+
+// a failed restore puts back what was there, so nothing is left half old and half new
+fn install_parts(staging: &Path, manifest: Manifest) -> Result<Restored> {
+    // every target touched so far and where its previous version went
+    let mut installed: Vec<(PathBuf, Option<PathBuf>)> = vec![];
+    let mut parts = vec![];
+
+    let result = (|| -> Result<()> {
+        for part in Part::ALL {
+            let unpacked = staging.join(part.archive_path());
+            if !unpacked.exists() {
+                continue;
+            }
+
+            let target = part.target();
+
+            let aside = if target.exists() {
+                let mut aside = target.clone().into_os_string();
+                aside.push(".before-restore");
+                let aside = PathBuf::from(aside);
+
+                remove_path(&aside)?;
+                move_path(&target, &aside)
+                    .map_err(|err| eyre!("Could not move {} aside: {}", target.display(), err))?;
+                Some(aside)
+            } else {
+                None
+            };
+            installed.push((target.clone(), aside));
+
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|err| eyre!("Could not create {}: {}", parent.display(), err))?;
+            }
+            move_path(&unpacked, &target)
+                .map_err(|err| eyre!("Could not restore {}: {}", target.display(), err))?;
+
+            parts.push(part.describe());
+        }
+
+        Ok(())
+    })();
+
+    if let Err(err) = result {
+        for (target, aside) in installed.iter().rev() {
+            let _ = remove_path(target);
+            if let Some(aside) = aside {
+                let _ = move_path(aside, target);
+            }
+        }
+
+        return Err(err.wrap_err("Restoring failed, the previous files were put back"));
+    }
+
+    let moved = installed.into_iter().filter_map(|(_, aside)| aside).collect();
+    Ok(Restored { manifest, moved, parts })
+}
+
+// aes-256-gcm with a pbkdf2 key, the header is authenticated along with the archive
+#[cfg(feature = "encryption")]
+mod cipher {
+    use std::{fs::File, io::{self, Read, Take, Write}};
+
+    use color_eyre::{eyre::eyre, Result};
+    use openssl::{hash::MessageDigest, symm::{Cipher, Crypter, Mode}};
+
+    use super::{HEADER_LEN, MAGIC, NONCE_LEN, SALT_LEN, TAG_LEN};
+
+    const ITERATIONS: u32 = 600_000;
+
+    fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<[u8; 32]> {
+        let mut key = [0; 32];
+        openssl::pkcs5::pbkdf2_hmac(passphrase.as_bytes(), salt, iterations as usize, MessageDigest::sha256(), &mut key)?;
+        Ok(key)
+    }
+
+    pub struct Encrypter {
+        inner: File,
+        crypter: Crypter,
+        buffer: Vec<u8>,
+    }
+
+    impl Encrypter {
+        pub fn new(mut inner: File, passphrase: &str) -> Result<Encrypter> {
+            let mut salt = [0; SALT_LEN];
+            let mut nonce = [0; NONCE_LEN];
+            openssl::rand::rand_bytes(&mut salt)?;
+            openssl::rand::rand_bytes(&mut nonce)?;
+
+            let mut header = MAGIC.to_vec();
+            header.extend_from_slice(&salt);
+            header.extend_from_slice(&ITERATIONS.to_be_bytes());
+            header.extend_from_slice(&nonce);
+            inner.write_all(&header)?;
+
+            let key = derive_key(passphrase, &salt, ITERATIONS)?;
+            let mut crypter = Crypter::new(Cipher::aes_256_gcm(), Mode::Encrypt, &key, Some(&nonce))?;
+            crypter.aad_update(&header)?;
+
+            Ok(Encrypter { inner, crypter, buffer: vec![] })
+        }
+
+        // appends the tag, the file is useless without it
+        pub fn finish(mut self) -> Result<File> {
+            let mut rest = [0; 32];
+            let written = self.crypter.finalize(&mut rest)?;
+            self.inner.write_all(&rest[..written])?;
+
+            let mut tag = [0; TAG_LEN];
+            self.crypter.get_tag(&mut tag)?;
+            self.inner.write_all(&tag)?;
+            self.inner.sync_all()?;
+
+            Ok(self.inner)
+        }
+    }
+
+    impl Write for Encrypter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffer.resize(buf.len() + 32, 0);
+            let written = self.crypter.update(buf, &mut self.buffer).map_err(io::Error::other)?;
+            self.inner.write_all(&self.buffer[..written])?;
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush()
+        }
+    }
+
+    pub struct Decrypter {
+        inner: Take<File>,
+        crypter: Crypter,
+        tag: [u8; TAG_LEN],
+        pending: Vec<u8>,
+        position: usize,
+        finished: bool,
+    }
+
+    impl Decrypter {
+        pub fn new(inner: Take<File>, header: &[u8; HEADER_LEN], tag: [u8; TAG_LEN], passphrase: &str) -> Result<Decrypter> {
+            let salt = &header[MAGIC.len()..MAGIC.len() + SALT_LEN];
+            let iterations = u32::from_be_bytes(header[MAGIC.len() + SALT_LEN..MAGIC.len() + SALT_LEN + 4].try_into()?);
+            let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+            if iterations == 0 {
+                return Err(eyre!("the backup header is damaged"));
+            }
+
+            let key = derive_key(passphrase, salt, iterations)?;
+            let mut crypter = Crypter::new(Cipher::aes_256_gcm(), Mode::Decrypt, &key, Some(nonce))?;
+            crypter.aad_update(header)?;
+
+            Ok(Decrypter { inner, crypter, tag, pending: vec![], position: 0, finished: false })
+        }
+    }
+
+    impl Read for Decrypter {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                if self.position < self.pending.len() {
+                    let count = buf.len().min(self.pending.len() - self.position);
+                    buf[..count].copy_from_slice(&self.pending[self.position..self.position + count]);
+                    self.position += count;
+                    return Ok(count);
+                }
+
+                if self.finished {
+                    return Ok(0);
+                }
+
+                let mut chunk = [0; 65536];
+                let read = self.inner.read(&mut chunk)?;
+                self.pending.resize(read + 32, 0);
+                self.position = 0;
+
+                let written = if read == 0 {
+                    self.finished = true;
+                    self.crypter.set_tag(&self.tag).map_err(io::Error::other)?;
+                    self.crypter.finalize(&mut self.pending)
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "authentication failed"))?
+                } else {
+                    self.crypter.update(&chunk[..read], &mut self.pending).map_err(io::Error::other)?
+                };
+
+                self.pending.truncate(written);
+            }
+        }
+    }
+}
+
+// nothing to encrypt with, new always fails so the rest is never reached
+#[cfg(not(feature = "encryption"))]
+mod cipher {
+    use std::{fs::File, io::{self, Read, Take, Write}};
+
+    use color_eyre::{eyre::eyre, Result};
+
+    use super::{HEADER_LEN, TAG_LEN};
+
+    const UNSUPPORTED: &str = "this signal-tui was built without the encryption feature, backups need it";
+
+    pub struct Encrypter(());
+
+    impl Encrypter {
+        pub fn new(_inner: File, _passphrase: &str) -> Result<Encrypter> {
+            Err(eyre!(UNSUPPORTED))
+        }
+
+        pub fn finish(self) -> Result<File> {
+            unreachable!()
+        }
+    }
+
+    impl Write for Encrypter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            unreachable!()
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            unreachable!()
+        }
+    }
+
+    pub struct Decrypter(());
+
+    impl Decrypter {
+        pub fn new(_inner: Take<File>, _header: &[u8; HEADER_LEN], _tag: [u8; TAG_LEN], _passphrase: &str) -> Result<Decrypter> {
+            Err(eyre!(UNSUPPORTED))
+        }
+    }
+
+    impl Read for Decrypter {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            unreachable!()
+        }
+    }
+}
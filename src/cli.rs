@@ -0,0 +1,601 @@
+use std::{collections::HashMap, io::{Read, Write}};
+
+use color_eyre::{eyre::eyre, Result};
+use rusqlite::Connection;
+
+use crate::{backup, config, db, export, fuzzy, import, java, signal, transport, types, ui};
+
+const USAGE: &str = "usage: signal-tui [command] [options]
+
+Without a command the full screen ui starts.
+
+commands:
+  send --to <number|group> [text]       send a message, the text is read from stdin if left out
+  accounts                              list the linked accounts
+  link [--print-uri]                    link this computer as a new device, --print-uri skips the qr code
+  history <conversation> [--limit <n>]  print the stored messages of a contact or group
+  tail [--json]                         print incoming messages as they arrive, --json as json lines
+  export [conversation] [--format <json|md|html|txt>] [--output <path>] [--from <date>] [--to <date>]
+                                        write a conversation, or every one of the account into a directory,
+                                        with copied attachments. dates are YYYY-MM-DD, both days included
+  import <file>...                      add messages from a json export or signal-cli receive --output=json logs,
+                                        messages that are stored already are skipped
+  passphrase [--remove]                 encrypt the stored messages or change their passphrase, --remove decrypts them
+  backup <file> [--signal-cli]          write an encrypted backup of the messages and config, --signal-cli adds
+                                        signal-cli's accounts and keys
+  restore <file> [--force]              put a backup back, what it replaces is kept as *.before-restore.
+                                        --force restores backups from newer versions
+
+options:
+  --account <number>  the account to use, default_account from the config or the only linked one
+
+Encrypted messages are opened with $SIGNAL_TUI_PASSPHRASE and backups with $SIGNAL_TUI_BACKUP_PASSPHRASE,
+otherwise the passphrase is asked for on the terminal.";
+
+// everything that runs without the full screen ui, args are the ones after the binary name
+pub fn run(args: Vec<String>) -> Result<()> {
+    let (command, rest) = args.split_first().ok_or_else(|| eyre!(USAGE))?;
+
+    match command.as_str() {
+        "send" => send(rest),
+        "accounts" => accounts(rest),
+        "link" => link(rest),
+        "history" => history(rest),
+        "tail" => tail(rest),
+        "export" => export(rest),
+        "import" => import(rest),
+        "passphrase" => passphrase(rest),
+        "backup" => backup(rest),
+        "restore" => restore(rest),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(eyre!("unknown command \"{}\"\n\n{}", other, USAGE)),
+    }
+}
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<&'static str, String>,
+    flags: Vec<&'static str>,
+}
+
+impl Args {
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name).map(|value| value.as_str())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.contains(&name)
+    }
+}
+
+// options take a value as "--name value" or "--name=value", flags dont, everything after "--" is positional
+fn parse(args: &[String], options: &[&'static str], flags: &[&'static str]) -> Result<Args> {
+    let mut parsed = Args { positional: vec![], options: HashMap::new(), flags: vec![] };
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            parsed.positional.extend(args.by_ref().cloned());
+            break;
+        }
+
+        if !arg.starts_with("--") {
+            parsed.positional.push(arg.clone());
+            continue;
+        }
+
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+
+        if let Some(option) = options.iter().find(|option| **option == name) {
+            let value = match value {
+                Some(value) => value,
+                None => args.next().cloned().ok_or_else(|| eyre!("{} needs a value", name))?,
+            };
+            parsed.options.insert(option, value);
+        } else if let Some(flag) = flags.iter().find(|flag| **flag == name) {
+            parsed.flags.push(flag);
+        } else {
+            return Err(eyre!("unknown option \"{}\"\n\n{}", name, USAGE));
+        }
+    }
+
+    Ok(parsed)
+}
+
+// the ui sets signal-cli and java up on its first start, here they have to be there already
+fn connect() -> Result<transport::Connection> {
+    let config = config::get();
+
+    if !config.transport.is_daemon() {
+        let bundled = config.signal_cli.path.is_none() && !config.signal_cli.use_path;
+
+        if bundled && !config.signal_cli_path().is_file() {
+            return Err(eyre!("signal-cli is not installed yet, start signal-tui once without a command to set it up"));
+        }
+
+        java::select();
+    }
+
+    transport::connect()
+}
+
+// --account, then default_account, then the only linked account
+fn pick_account(connection: &mut transport::Connection, requested: Option<&str>) -> Result<String> {
+    let accounts: Vec<String> = signal::list_accounts(&mut connection.writer, &mut connection.reader)
+        .map_err(|err| eyre!("Listing the accounts failed: {}", err))?
+        .into_iter()
+        .map(|account| account.number)
+        .collect();
+
+    let wanted = requested.map(|number| number.to_string()).or(config::get().default_account.clone());
+
+    let account = match wanted {
+        Some(number) if accounts.contains(&number) => number,
+        Some(number) => return Err(eyre!("{} is not linked, linked accounts: {}", number, accounts.join(", "))),
+        None if accounts.len() == 1 => accounts[0].clone(),
+        None if accounts.is_empty() => return Err(eyre!("no account linked, run signal-tui link first")),
+        None => return Err(eyre!("more than one account linked, pick one with --account: {}", accounts.join(", "))),
+    };
+
+    connection.writer.account = Some(account.clone());
+
+    Ok(account)
+}
+
+// 0 = group, 1 = contact and the id, exact names only unless fuzzy is set
+fn resolve(
+    query: &str,
+    groups: &[types::SignalGroup],
+    contacts: &[types::SignalContact],
+    fuzzy: bool,
+) -> Option<(usize, String)> {
+    let lowercase = query.to_lowercase();
+
+    if let Some(group) = groups.iter().find(|group| group.id == query || group.name.to_lowercase() == lowercase) {
+        return Some((0, group.id.clone()));
+    }
+
+    let contact = contacts.iter().find(|contact| {
+        contact.uuid == query
+            || contact.number.as_deref() == Some(query)
+            || contact.username.as_deref() == Some(query)
+            || crate::app::contact_name(contact).to_lowercase() == lowercase
+    });
+
+    if let Some(contact) = contact {
+        return Some((1, contact.uuid.clone()));
+    }
+
+    if !fuzzy {
+        return None;
+    }
+
+    let best_group = groups.iter()
+        .filter_map(|group| Some((fuzzy::score(query, &group.name)?, 0, group.id.clone())));
+    let best_contact = contacts.iter()
+        .filter_map(|contact| Some((fuzzy::score(query, &crate::app::contact_name(contact))?, 1, contact.uuid.clone())));
+
+    best_group.chain(best_contact)
+        .max_by_key(|(score, _, _)| *score)
+        .map(|(_, kind, id)| (kind, id))
+}
+
+fn send(args: &[String]) -> Result<()> {
+    let args = parse(args, &["--to", "--account"], &[])?;
+    let to = args.option("--to").ok_or_else(|| eyre!("send needs --to <number|group>"))?;
+
+    let text = if args.positional.is_empty() {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text.trim_end_matches('\n').to_string()
+    } else {
+        args.positional.join(" ")
+    };
+
+    if text.trim().is_empty() {
+        return Err(eyre!("nothing to send"));
+    }
+
+    let mut connection = connect()?;
+    let account = pick_account(&mut connection, args.option("--account"))?;
+
+    // contacts are stored by uuid so the message shows up in the ui, other numbers can still be messaged
+    let (groups, contacts) = signal::sync(&mut connection.writer, &mut connection.reader).map_err(|err| eyre!("syncing failed: {}", err))?;
+    let (dest_type, dest_id) = match resolve(to, &groups, &contacts, false) {
+        Some(destination) => destination,
+        None if to.starts_with('+') => (1, to.to_string()),
+        None => return Err(eyre!("no contact or group called \"{}\"", to)),
+    };
+
+    let db = open_db()?;
+    let id = signal::send_msg_with(&mut connection.writer, text, serde_json::json!({}), dest_id, dest_type, &db, account);
+    let result = signal::wait_for(&mut connection.reader, &id).map_err(|err| eyre!("sending failed: {}", err))?;
+
+    // one result per recipient, anything but SUCCESS means it didnt arrive there
+    let failed: Vec<String> = result["results"].as_array().into_iter().flatten()
+        .filter(|result| result["type"].as_str() != Some("SUCCESS"))
+        .map(|result| format!(
+            "{}: {}",
+            result["recipientAddress"]["number"].as_str().or(result["recipientAddress"]["uuid"].as_str()).unwrap_or("?"),
+            result["type"].as_str().unwrap_or("unknown error")
+        ))
+        .collect();
+
+    if let Some(timestamp) = result["timestamp"].as_u64() {
+        db::mark_sent(&db, &id, timestamp)?;
+    }
+
+    if !failed.is_empty() {
+        return Err(eyre!("not delivered to {}", failed.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn accounts(args: &[String]) -> Result<()> {
+    parse(args, &[], &[])?;
+    let mut connection = connect()?;
+
+    let accounts = signal::list_accounts(&mut connection.writer, &mut connection.reader)
+        .map_err(|err| eyre!("Listing the accounts failed: {}", err))?;
+
+    for account in accounts {
+        println!("{}", account.number);
+    }
+
+    Ok(())
+}
+
+fn link(args: &[String]) -> Result<()> {
+    let args = parse(args, &[], &["--print-uri"])?;
+    let mut connection = connect()?;
+
+    let uri = signal::link_device(&mut connection.writer, &mut connection.reader).map_err(|err| eyre!("linking failed: {}", err))?;
+
+    if !args.flag("--print-uri") {
+        println!("{}", ui::qr_text(&uri));
+        eprintln!("Scan the code with Signal on your phone, or open this link on it:");
+    }
+    println!("{}", uri);
+
+    signal::finish_link(&mut connection.writer, &mut connection.reader, uri).map_err(|err| eyre!("linking failed: {}", err))?;
+    eprintln!("Linked");
+
+    Ok(())
+}
+
+fn history(args: &[String]) -> Result<()> {
+    let args = parse(args, &["--account", "--limit"], &[])?;
+
+    let query = args.positional.join(" ");
+    if query.is_empty() {
+        return Err(eyre!("history needs a contact or group"));
+    }
+
+    let limit = match args.option("--limit") {
+        Some(limit) => Some(limit.parse::<usize>().map_err(|_| eyre!("--limit needs a number, not \"{}\"", limit))?),
+        None => None,
+    };
+
+    let mut connection = connect()?;
+    let account = pick_account(&mut connection, args.option("--account"))?;
+    let (groups, contacts) = signal::sync(&mut connection.writer, &mut connection.reader).map_err(|err| eyre!("syncing failed: {}", err))?;
+
+    let (kind, id) = resolve(&query, &groups, &contacts, true).ok_or_else(|| eyre!("no contact or group matches \"{}\"", query))?;
+
+    let db = open_db()?;
+    let messages = db::messages(&db, &account, kind, &id)?;
+    let skip = limit.map(|limit| messages.len().saturating_sub(limit)).unwrap_or(0);
+
+    for message in messages.iter().skip(skip) {
+        println!(
+            "[{}] {}: {}",
+            crate::app::format_timestamp(message.timestamp, "%Y-%m-%d %H:%M"),
+            message.source_name,
+            message.message
+        );
+    }
+
+    Ok(())
+}
+
+fn tail(args: &[String]) -> Result<()> {
+    let args = parse(args, &["--account"], &["--json"])?;
+    let json = args.flag("--json");
+
+    // the reader stores what comes in, so the database has to be unlocked and set up first
+    drop(open_db()?);
+
+    let mut connection = connect()?;
+
+    // every linked account unless one was asked for
+    let accounts: Vec<String> = match args.option("--account") {
+        Some(_) => vec![pick_account(&mut connection, args.option("--account"))?],
+        None => signal::list_accounts(&mut connection.writer, &mut connection.reader)
+            .map_err(|err| eyre!("Listing the accounts failed: {}", err))?
+            .into_iter()
+            .map(|account| account.number)
+            .collect(),
+    };
+
+    if !config::get().transport.is_daemon() {
+        for account in &accounts {
+            signal::subscribe_receive(&mut connection.writer, account);
+        }
+    }
+
+    let (events_tx, events_rx) = std::sync::mpsc::channel::<types::ReaderEvent>();
+    let mut reader = connection.reader;
+
+    std::thread::spawn(move || {
+        let reason = match signal::read_events_countinously(&mut reader, &events_tx) {
+            Ok(()) => "signal-cli went away".to_string(),
+            Err(err) => format!("reading from signal-cli failed: {}", err),
+        };
+        let _ = events_tx.send(types::ReaderEvent::Closed(reason));
+    });
+
+    // stops once whatever reads the output goes away, eg. grep -m 1
+    let mut out = std::io::stdout().lock();
+
+    for event in events_rx {
+        let incoming = match event {
+            types::ReaderEvent::Message(incoming) => incoming,
+            types::ReaderEvent::Closed(reason) => return Err(eyre!(reason)),
+            types::ReaderEvent::Error { message, .. } => {
+                eprintln!("{}", message);
+                continue;
+            }
+            types::ReaderEvent::Response { .. } | types::ReaderEvent::Changed => continue,
+        };
+
+        if !accounts.contains(&incoming.account_number) {
+            continue;
+        }
+
+        let written = if json {
+            writeln!(out, "{}", serde_json::json!({
+                "account": incoming.account_number,
+                "conversation": incoming.conversation_id,
+                "group": incoming.group_id,
+                "sourceNumber": incoming.source_number,
+                "sourceName": incoming.source_name,
+                "timestamp": incoming.timestamp,
+                "message": incoming.message,
+            }))
+        } else {
+            writeln!(
+                out,
+                "[{}] {}: {}",
+                crate::app::format_timestamp(incoming.timestamp, "%Y-%m-%d %H:%M"),
+                incoming.source_name,
+                incoming.message
+            )
+        };
+
+        if written.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn export(args: &[String]) -> Result<()> {
+    let args = parse(args, &["--account", "--format", "--output", "--from", "--to"], &[])?;
+    let query = args.positional.join(" ");
+    let output = args.option("--output").map(std::path::PathBuf::from);
+
+    // --format, then the extension of --output, then markdown
+    let format = match args.option("--format") {
+        Some(name) => export::Format::parse(name).ok_or_else(|| eyre!("unknown format \"{}\", use json, md, html or txt", name))?,
+        None => output.as_deref().and_then(export::Format::from_path).unwrap_or(export::Format::Markdown),
+    };
+
+    let from = args.option("--from").map(|date| export::parse_date(date, false)).transpose()?;
+    let to = args.option("--to").map(|date| export::parse_date(date, true)).transpose()?;
+
+    let mut connection = connect()?;
+    let account = pick_account(&mut connection, args.option("--account"))?;
+    let (groups, contacts) = signal::sync(&mut connection.writer, &mut connection.reader).map_err(|err| eyre!("syncing failed: {}", err))?;
+
+    let db = open_db()?;
+    let conversations = export::conversations(&db, &account, &groups, &contacts)?;
+
+    if query.is_empty() {
+        let dir = output.unwrap_or_else(|| export::default_path(&account, None, format));
+        let (files, messages) = export::export_all(&db, &account, &conversations, format, &dir, from, to)?;
+
+        eprintln!("Exported {} messages in {} conversations to {}", messages, files, dir.display());
+        return Ok(());
+    }
+
+    let (kind, id) = resolve(&query, &groups, &contacts, true).ok_or_else(|| eyre!("no contact or group matches \"{}\"", query))?;
+    let conversation = conversations.into_iter()
+        .find(|conversation| conversation.kind == kind && conversation.id == id)
+        .ok_or_else(|| eyre!("no messages stored for \"{}\"", query))?;
+
+    let path = output.unwrap_or_else(|| export::default_path(&account, Some(&conversation.name), format));
+    let messages = export::export_conversation(&db, &account, &conversation, format, &path, from, to)?;
+
+    eprintln!("Exported {} messages to {}", messages, path.display());
+
+    Ok(())
+}
+
+// works on data.db alone, signal-cli isnt needed
+fn import(args: &[String]) -> Result<()> {
+    let args = parse(args, &["--account"], &[])?;
+
+    if args.positional.is_empty() {
+        return Err(eyre!("import needs at least one file"));
+    }
+
+    let mut db = open_db()?;
+
+    for file in &args.positional {
+        let summary = import::import_file(&mut db, std::path::Path::new(file), args.option("--account"))?;
+
+        eprintln!(
+            "{}: {} imported, {} skipped{}",
+            file,
+            summary.imported,
+            summary.skipped,
+            if summary.unreadable > 0 { format!(", {} lines not understood", summary.unreadable) } else { String::new() }
+        );
+    }
+
+    Ok(())
+}
+
+// nothing else may have data.db open, the file gets replaced
+fn passphrase(args: &[String]) -> Result<()> {
+    let args = parse(args, &[], &["--remove"])?;
+    drop(open_db()?);
+
+    if args.flag("--remove") {
+        if !db::is_encrypted() {
+            return Err(eyre!("the messages are not encrypted"));
+        }
+
+        db::change_passphrase(None)?;
+        eprintln!("The messages are stored unencrypted now");
+        return Ok(());
+    }
+
+    let passphrase = read_secret("New passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(eyre!("the passphrase can not be empty, use --remove to decrypt"));
+    }
+    if read_secret("Again: ")? != passphrase {
+        return Err(eyre!("the passphrases do not match, nothing was changed"));
+    }
+
+    db::change_passphrase(Some(&passphrase))?;
+    eprintln!("The messages are encrypted");
+
+    Ok(())
+}
+
+fn backup_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var("SIGNAL_TUI_BACKUP_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+
+    let passphrase = read_secret("Backup passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(eyre!("backups need a passphrase"));
+    }
+    if confirm && read_secret("Again: ")? != passphrase {
+        return Err(eyre!("the passphrases do not match"));
+    }
+
+    Ok(passphrase)
+}
+
+// data.db is snapshotted, the other files are copied as they are so signal-cli should not be running with --signal-cli
+fn backup(args: &[String]) -> Result<()> {
+    let args = parse(args, &[], &["--signal-cli"])?;
+    let [file] = args.positional.as_slice() else {
+        return Err(eyre!("backup needs the file to write"));
+    };
+
+    // the snapshot is made through a connection, which needs the key of an encrypted database
+    drop(open_db()?);
+
+    let passphrase = backup_passphrase(true)?;
+    let manifest = backup::create(std::path::Path::new(file), &passphrase, args.flag("--signal-cli"))?;
+
+    let size: u64 = manifest.files.iter().map(|file| file.size).sum();
+    eprintln!("Backed up {} files ({} KiB) to {}", manifest.files.len(), size / 1024, file);
+
+    Ok(())
+}
+
+fn restore(args: &[String]) -> Result<()> {
+    let args = parse(args, &[], &["--force"])?;
+    let [file] = args.positional.as_slice() else {
+        return Err(eyre!("restore needs the backup file"));
+    };
+
+    let passphrase = backup_passphrase(false)?;
+    let restored = backup::restore(std::path::Path::new(file), &passphrase, args.flag("--force"))?;
+
+    eprintln!(
+        "Restored the {} from {}",
+        restored.parts.join(", "),
+        crate::app::format_timestamp(restored.manifest.created as u64, "%Y-%m-%d %H:%M")
+    );
+    for path in &restored.moved {
+        eprintln!("The previous one is kept in {}", path.display());
+    }
+    if restored.manifest.database_encrypted {
+        eprintln!("The messages are encrypted with the passphrase they had when the backup was made");
+    }
+
+    Ok(())
+}
+
+// without echo, on stderr so it stays out of pipes
+fn read_secret(prompt: &str) -> Result<String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!("no terminal to ask for the passphrase, set SIGNAL_TUI_PASSPHRASE"));
+    }
+
+    eprint!("{}", prompt);
+    crossterm::terminal::enable_raw_mode()?;
+
+    let mut secret = String::new();
+    let result = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break Err(eyre!("cancelled")),
+                KeyCode::Esc => break Err(eyre!("cancelled")),
+                KeyCode::Backspace => {
+                    secret.pop();
+                }
+                KeyCode::Char(c) => secret.push(c),
+                _ => {}
+            },
+            Ok(Event::Paste(pasted)) => secret.push_str(&pasted),
+            Ok(_) => {}
+            Err(err) => break Err(err.into()),
+        }
+    };
+
+    crossterm::terminal::disable_raw_mode()?;
+    eprintln!();
+
+    // the same as the ui, which trims what it reads
+    result.map(|()| secret.trim().to_string())
+}
+
+fn open_db() -> Result<Connection> {
+    db::mark_in_use()?;
+
+    if db::is_encrypted() {
+        let passphrase = match std::env::var("SIGNAL_TUI_PASSPHRASE") {
+            Ok(passphrase) => passphrase,
+            Err(_) => read_secret("Passphrase: ")?,
+        };
+
+        if !db::unlock(&passphrase)? {
+            return Err(eyre!("wrong passphrase for {}", db::path().display()));
+        }
+    }
+
+    let db = db::open()?;
+    db::init(&db)?;
+
+    Ok(db)
+}
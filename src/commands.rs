@@ -0,0 +1,400 @@
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scope {
+    Any,
+    Group,
+    Contact,
+}
+
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub description: &'static str,
+    pub scope: Scope,
+}
+
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo { name: "me", args: "<action>", description: "send an action, eg. /me waves", scope: Scope::Any },
+    CommandInfo { name: "attach", args: "<path> [caption]", description: "send a file", scope: Scope::Any },
+    CommandInfo { name: "search", args: "<text>", description: "search this conversation", scope: Scope::Any },
+    CommandInfo { name: "expire", args: "<duration|off>", description: "disappearing messages, eg. 30s, 5m, 1h, 1d, 1w", scope: Scope::Any },
+    CommandInfo { name: "block", args: "", description: "block this conversation", scope: Scope::Any },
+    CommandInfo { name: "unblock", args: "", description: "unblock this conversation", scope: Scope::Any },
+    CommandInfo { name: "nick", args: "<name>", description: "set a nickname only you can see", scope: Scope::Contact },
+    CommandInfo { name: "topic", args: "<text>", description: "set the group description", scope: Scope::Group },
+    CommandInfo { name: "rename", args: "<name>", description: "rename the group", scope: Scope::Group },
+    CommandInfo { name: "leave", args: "", description: "leave the group", scope: Scope::Group },
+    CommandInfo { name: "help", args: "", description: "list commands", scope: Scope::Any },
+];
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Me(String),
+    Attach { path: PathBuf, caption: String },
+    Search(String),
+    // seconds, 0 is off
+    Expire(u32),
+    Block,
+    Unblock,
+    Nick(String),
+    Topic(String),
+    Rename(String),
+    Leave,
+    Help,
+}
+
+// what the composer holds, "//text" is an escaped message starting with a slash
+pub enum Input {
+    Message(String),
+    Command(Result<Command, String>),
+}
+
+pub fn parse_input(text: &str, is_group: bool) -> Input {
+    if let Some(escaped) = text.strip_prefix("//") {
+        return Input::Message(format!("/{}", escaped));
+    }
+
+    match text.strip_prefix('/') {
+        Some(command) => Input::Command(parse(command, is_group)),
+        None => Input::Message(text.to_string()),
+    }
+}
+
+fn split_command(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (text, ""),
+    }
+}
+
+fn find(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|info| info.name == name)
+}
+
+pub fn usage(info: &CommandInfo) -> String {
+    if info.args.is_empty() {
+        format!("/{}", info.name)
+    } else {
+        format!("/{} {}", info.name, info.args)
+    }
+}
+
+fn parse(text: &str, is_group: bool) -> Result<Command, String> {
+    let (name, args) = split_command(text);
+
+    let info = find(name).ok_or_else(|| format!("Unknown command /{}, try /help", name))?;
+
+    match info.scope {
+        Scope::Group if !is_group => return Err(format!("/{} only works in groups", name)),
+        Scope::Contact if is_group => return Err(format!("/{} only works in direct messages", name)),
+        _ => {}
+    }
+
+    let takes_args = !info.args.is_empty();
+    let required = info.args.starts_with('<');
+
+    if required && args.is_empty() {
+        return Err(format!("Usage: {}", usage(info)));
+    }
+    if !takes_args && !args.is_empty() {
+        return Err(format!("/{} takes no arguments", name));
+    }
+
+    let command = match name {
+        "me" => Command::Me(args.to_string()),
+        "attach" => {
+            let (path, caption) = split_path(args);
+            let path = expand_home(&path);
+
+            if !path.is_file() {
+                return Err(format!("No such file: {}", path.display()));
+            }
+
+            // signal-cli resolves relative paths against its own working directory
+            let path = std::fs::canonicalize(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+            Command::Attach { path, caption: caption.to_string() }
+        }
+        "search" => Command::Search(args.to_string()),
+        "expire" => Command::Expire(
+            parse_duration(args).ok_or_else(|| format!("Invalid duration \"{}\", eg. 30s, 5m, 1h, 1d, 1w or off", args))?
+        ),
+        "block" => Command::Block,
+        "unblock" => Command::Unblock,
+        "nick" => Command::Nick(args.to_string()),
+        "topic" => Command::Topic(args.to_string()),
+        "rename" => Command::Rename(args.to_string()),
+        "leave" => Command::Leave,
+        "help" => Command::Help,
+        _ => unreachable!("command {} has no parser", name),
+    };
+
+    Ok(command)
+}
+
+// "1h", "90m", "2d 12h", "off"
+pub fn parse_duration(text: &str) -> Option<u32> {
+    if matches!(text, "off" | "0") {
+        return Some(0);
+    }
+
+    let mut total: u64 = 0;
+
+    for part in text.split_whitespace() {
+        let split = part.find(|c: char| !c.is_ascii_digit())?;
+        let (number, unit) = part.split_at(split);
+        let number: u64 = number.parse().ok()?;
+
+        let multiplier = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 60 * 60 * 24,
+            "w" => 60 * 60 * 24 * 7,
+            _ => return None,
+        };
+
+        total = total.checked_add(number.checked_mul(multiplier)?)?;
+    }
+
+    if total == 0 {
+        return None;
+    }
+
+    u32::try_from(total).ok()
+}
+
+pub fn format_duration(seconds: u32) -> String {
+    const UNITS: [(u32, &str); 5] = [(604800, "w"), (86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+
+    let mut rest = seconds;
+    let mut parts = vec![];
+
+    for (size, unit) in UNITS {
+        if rest >= size {
+            parts.push(format!("{}{}", rest / size, unit));
+            rest %= size;
+        }
+    }
+
+    parts.join(" ")
+}
+
+// paths with spaces can be quoted
+fn split_path(args: &str) -> (String, &str) {
+    if let Some(rest) = args.strip_prefix('"')
+        && let Some(end) = rest.find('"') {
+        return (rest[..end].to_string(), rest[end + 1..].trim());
+    }
+
+    let (path, caption) = split_command(args);
+    (path.to_string(), caption)
+}
+
+pub fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => match directories::BaseDirs::new() {
+            Some(dirs) => dirs.home_dir().join(rest),
+            None => PathBuf::from(path),
+        },
+        None => PathBuf::from(path),
+    }
+}
+
+// usage line for the command being typed, shown above the input
+pub fn hint(text: &str) -> Option<String> {
+    let text = text.strip_prefix('/')?;
+    if text.starts_with('/') || text.contains('\n') {
+        return None;
+    }
+
+    let (name, _) = split_command(text);
+    let typing_name = !text.contains(char::is_whitespace);
+
+    if let Some(info) = find(name) {
+        return Some(format!("{} - {}", usage(info), info.description));
+    }
+
+    if typing_name {
+        let matches: Vec<String> = COMMANDS.iter()
+            .filter(|info| info.name.starts_with(name))
+            .map(|info| format!("/{}", info.name))
+            .collect();
+
+        if !matches.is_empty() {
+            return Some(matches.join(" "));
+        }
+    }
+
+    Some(format!("Unknown command /{}", name))
+}
+
+// the completed text, or None if there is nothing to complete
+pub fn complete(text: &str) -> Option<String> {
+    let command = text.strip_prefix('/')?;
+    if command.starts_with('/') || command.contains('\n') {
+        return None;
+    }
+
+    match command.split_once(' ') {
+        None => {
+            let names: Vec<&str> = COMMANDS.iter()
+                .map(|info| info.name)
+                .filter(|name| name.starts_with(command))
+                .collect();
+
+            let prefix = common_prefix(&names)?;
+
+            if names.len() == 1 {
+                Some(format!("/{} ", prefix))
+            } else if prefix.len() > command.len() {
+                Some(format!("/{}", prefix))
+            } else {
+                None
+            }
+        }
+        Some(("attach", path)) if !path.starts_with('"') && !path.contains(' ') => {
+            complete_path(path).map(|path| format!("/attach {}", path))
+        }
+        Some(_) => None,
+    }
+}
+
+fn common_prefix(words: &[&str]) -> Option<String> {
+    let first = words.first()?;
+
+    let mut len = first.len();
+    for word in &words[1..] {
+        let shared = first.char_indices()
+            .zip(word.chars())
+            .find(|((_, a), b)| a != b)
+            .map(|((i, _), _)| i)
+            .unwrap_or(first.len().min(word.len()));
+        len = len.min(shared);
+    }
+
+    Some(first[..len].to_string())
+}
+
+fn complete_path(partial: &str) -> Option<String> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+
+    let search_dir = if dir.is_empty() {
+        PathBuf::from(".")
+    } else {
+        expand_home(dir)
+    };
+
+    let mut names: Vec<String> = std::fs::read_dir(&search_dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            // hidden files only when asked for
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            if Path::new(&search_dir).join(&name).is_dir() {
+                Some(format!("{}/", name))
+            } else {
+                Some(name)
+            }
+        })
+        .collect();
+    names.sort();
+
+    let refs: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    let completed = common_prefix(&refs)?;
+
+    if completed.len() == prefix.len() && names.len() > 1 {
+        return None;
+    }
+
+    Some(format!("{}{}", dir, completed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_durations() {
+        let cases = [
+            ("30s", Some(30)),
+            ("5m", Some(300)),
+            ("1h 30m", Some(5400)),
+            ("2d", Some(172800)),
+            ("1w", Some(604800)),
+            ("off", Some(0)),
+            ("0", Some(0)),
+            ("", None),
+            ("0s", None),
+            ("5", None),
+            ("5x", None),
+            ("5M", None),
+            ("m", None),
+            ("-5m", None),
+            ("1h30m", None),
+            ("5 m", None),
+            // more seconds than fit in the u32 signal-cli takes
+            ("10000w", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(parse_duration(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn complete_commands() {
+        let cases = [
+            ("/e", Some("/expire ")),
+            ("/b", Some("/block ")),
+            ("/un", Some("/unblock ")),
+            ("/me", Some("/me ")),
+            // more than one command starts with nothing
+            ("/", None),
+            ("/x", None),
+            ("//e", None),
+            ("e", None),
+            ("/e\nx", None),
+            ("/expire 5", None),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(complete(text).as_deref(), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn complete_attach_paths() {
+        let dir = std::env::temp_dir().join(format!("signal-tui-complete-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("photos")).unwrap();
+        for name in ["notes-1.txt", "notes-2.txt", "report.pdf", ".hidden"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+
+        let base = dir.display().to_string();
+        let cases = [
+            ("r", Some("report.pdf")),
+            ("n", Some("notes-")),
+            ("notes-", None),
+            ("p", Some("photos/")),
+            (".h", Some(".hidden")),
+            ("x", None),
+        ];
+
+        for (partial, expected) in cases {
+            let text = format!("/attach {}/{}", base, partial);
+            let expected = expected.map(|rest| format!("/attach {}/{}", base, rest));
+            assert_eq!(complete(&text), expected, "{:?}", partial);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
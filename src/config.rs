@@ -0,0 +1,217 @@
+use std::{collections::HashMap, path::PathBuf, sync::OnceLock, time::Duration};
+
+use chrono::format::{Item, StrftimeItems};
+use directories::ProjectDirs;
+use serde::Deserialize;
+
+use crate::notify::NotificationSettings;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// anything shorter redraws so often it keeps a core busy
+const MIN_POLL_INTERVAL_MS: u64 = 10;
+// narrower cuts every name off, wider leaves no room for the conversation on most terminals
+pub const MIN_SIDEBAR_WIDTH: u16 = 12;
+pub const MAX_SIDEBAR_WIDTH: u16 = 80;
+// install::MANIFEST pins the checksums of this one
+pub const DEFAULT_SIGNAL_CLI_VERSION: &str = "0.13.14";
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    // where data.db, the debug log and the downloaded signal-cli live
+    pub data_dir: Option<PathBuf>,
+    // skips the account picker if this account is linked
+    pub default_account: Option<String>,
+    // strftime format shown in front of messages, empty to hide timestamps
+    pub timestamp_format: String,
+    // how long to wait for input before redrawing
+    pub poll_interval_ms: u64,
+    // dark, light, high-contrast or the name of a file in the themes dir
+    pub theme: String,
+    // "default" or "vim"
+    pub keymap: String,
+    // per context overrides, eg. [keys.sidebar] "ctrl-j" = "down"
+    pub keys: HashMap<String, HashMap<String, String>>,
+    pub signal_cli: SignalCliConfig,
+    // spawn signal-cli (default) or connect to a running daemon, eg. [transport] type = "socket" path = "..."
+    pub transport: crate::transport::Transport,
+    pub notifications: NotificationSettings,
+    pub layout: LayoutConfig,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SignalCliConfig {
+    // use this signal-cli instead of downloading one
+    pub path: Option<PathBuf>,
+    // use the signal-cli found on $PATH instead of downloading one
+    pub use_path: bool,
+    // version to download if no path is set
+    pub version: String,
+    // download the native build that doesnt need java, only exists for linux x86_64
+    pub native: bool,
+    // install from this release tarball instead of downloading it, for machines without internet
+    pub tarball: Option<PathBuf>,
+    // expected sha-256 of the tarball, needed for versions the app doesnt know the checksum of when github cant be reached
+    pub sha256: Option<String>,
+    // check the tarball checksum before installing
+    pub verify: bool,
+    // extra arguments passed to signal-cli before the command, eg. ["--trust-new-identities", "always"]
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    // starting width, < and > change it while running. narrow terminals show it as an overlay instead
+    pub sidebar_width: u16,
+    pub sort_by_recent: bool,
+    // list the conversations of every linked account together, tagged with the account
+    pub unified_sidebar: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            data_dir: None,
+            default_account: None,
+            timestamp_format: "%H:%M".to_string(),
+            poll_interval_ms: 100,
+            theme: "dark".to_string(),
+            keymap: "default".to_string(),
+            keys: HashMap::new(),
+            signal_cli: SignalCliConfig::default(),
+            transport: crate::transport::Transport::default(),
+            notifications: NotificationSettings::default(),
+            layout: LayoutConfig::default(),
+        }
+    }
+}
+
+impl Default for SignalCliConfig {
+    fn default() -> Self {
+        SignalCliConfig {
+            path: None,
+            use_path: false,
+            version: DEFAULT_SIGNAL_CLI_VERSION.to_string(),
+            native: false,
+            tarball: None,
+            sha256: None,
+            verify: true,
+            args: vec![],
+        }
+    }
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        LayoutConfig {
+            sidebar_width: 30,
+            sort_by_recent: false,
+            unified_sidebar: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir.clone().unwrap_or_else(|| project_dirs().data_local_dir().to_path_buf())
+    }
+
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    // where signal-cli keeps accounts and attachments, --config in signal_cli.args moves it
+    pub fn signal_cli_data_dir(&self) -> PathBuf {
+        let mut args = self.signal_cli.args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(dir) = arg.strip_prefix("--config=") {
+                return PathBuf::from(dir);
+            }
+            if (arg == "--config" || arg == "-c")
+                && let Some(dir) = args.next() {
+                return PathBuf::from(dir);
+            }
+        }
+
+        // signal-cli uses $XDG_DATA_HOME or ~/.local/share on every platform
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| directories::BaseDirs::new().map(|dirs| dirs.home_dir().join(".local/share")))
+            .unwrap_or_default();
+
+        data_home.join("signal-cli")
+    }
+
+    // the bundled signal-cli, or the one from the config
+    pub fn signal_cli_path(&self) -> PathBuf {
+        match &self.signal_cli.path {
+            Some(path) => path.clone(),
+            None if self.signal_cli.use_path => crate::install::find_in_path("signal-cli")
+                .unwrap_or_else(|| PathBuf::from("signal-cli")),
+            None => match std::env::consts::OS {
+                "windows" => self.data_dir().join("signal-cli/bin/signal-cli.bat"),
+                _ => self.data_dir().join("signal-cli/bin/signal-cli")
+            },
+        }
+    }
+}
+
+pub fn project_dirs() -> ProjectDirs {
+    ProjectDirs::from(
+        "dev",
+        "cyteon",
+        "signal-tui"
+    ).unwrap()
+}
+
+pub fn config_path() -> PathBuf {
+    project_dirs().config_dir().join("config.toml")
+}
+
+// reads the config file, a missing file just means defaults
+pub fn load() -> color_eyre::Result<()> {
+    let path = config_path();
+
+    let config = if std::fs::exists(&path)? {
+        let content = std::fs::read_to_string(&path)?;
+
+        let config: Config = toml::from_str(&content)
+            .map_err(|err| color_eyre::eyre::eyre!("Invalid config file {}:\n{}", path.display(), err))?;
+
+        if StrftimeItems::new(&config.timestamp_format).any(|item| item == Item::Error) {
+            return Err(color_eyre::eyre::eyre!(
+                "Invalid config file {}:\ntimestamp_format \"{}\" is not a valid strftime format",
+                path.display(), config.timestamp_format
+            ));
+        }
+
+        if config.poll_interval_ms < MIN_POLL_INTERVAL_MS {
+            return Err(color_eyre::eyre::eyre!(
+                "Invalid config file {}:\npoll_interval_ms is {}, it has to be at least {}",
+                path.display(), config.poll_interval_ms, MIN_POLL_INTERVAL_MS
+            ));
+        }
+
+        if !(MIN_SIDEBAR_WIDTH..=MAX_SIDEBAR_WIDTH).contains(&config.layout.sidebar_width) {
+            return Err(color_eyre::eyre::eyre!(
+                "Invalid config file {}:\nlayout.sidebar_width is {}, it has to be between {} and {}",
+                path.display(), config.layout.sidebar_width, MIN_SIDEBAR_WIDTH, MAX_SIDEBAR_WIDTH
+            ));
+        }
+
+        config
+    } else {
+        Config::default()
+    };
+
+    let _ = CONFIG.set(config);
+
+    Ok(())
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
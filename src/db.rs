@@ -1,3 +1,175 @@
+use std::{collections::HashMap, fs::{File, TryLockError}, path::{Path, PathBuf}, sync::Mutex};
+
+use color_eyre::{eyre::eyre, Result};
+use rusqlite::{Connection, ErrorCode};
+
+use crate::{notify::NotificationLevel, types::{self, ConversationStats, StoredMessage}};
+
+// the passphrase of an encrypted data.db, set once it is unlocked
+static KEY: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn path() -> PathBuf {
+    crate::config::get().data_dir().join("data.db")
+}
+
+// every signal-tui using data.db holds this lock shared until it exits, restores need it to themselves
+static IN_USE: Mutex<Option<File>> = Mutex::new(None);
+
+fn lock_file() -> Result<File> {
+    let dir = crate::config::get().data_dir();
+    std::fs::create_dir_all(&dir)?;
+
+    Ok(File::options().create(true).truncate(false).write(true).open(dir.join("signal-tui.lock"))?)
+}
+
+pub fn mark_in_use() -> Result<()> {
+    let file = lock_file()?;
+
+    match file.try_lock_shared() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => return Err(eyre!("a backup is being restored, try again once it is done")),
+        Err(TryLockError::Error(err)) => return Err(err.into()),
+    }
+
+    *IN_USE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+// None while another signal-tui uses data.db, nobody else gets in until the file is dropped
+pub fn lock_exclusive() -> Result<Option<File>> {
+    let file = lock_file()?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+fn set_key(db: &Connection, key: &str) -> rusqlite::Result<()> {
+    db.pragma_update(None, "key", key)?;
+
+    // the first key turns on sqlcipher's logging to stderr, which would print every wrong passphrase over the ui
+    let _ = db.query_row("PRAGMA cipher_log_level = NONE", [], |_| Ok(()));
+
+    Ok(())
+}
+
+// every connection to data.db goes through here so an encrypted one gets its key
+pub fn open() -> rusqlite::Result<Connection> {
+    let db = Connection::open(path())?;
+
+    if let Some(key) = KEY.lock().unwrap().as_deref() {
+        set_key(&db, key)?;
+    }
+
+    Ok(db)
+}
+
+// builds without the encryption feature link plain sqlite, which ignores keys
+pub fn cipher_available() -> bool {
+    Connection::open_in_memory()
+        .and_then(|db| db.query_row("PRAGMA cipher_version", [], |row| row.get::<_, String>(0)))
+        .is_ok()
+}
+
+fn not_a_database(err: &rusqlite::Error) -> bool {
+    matches!(err, rusqlite::Error::SqliteFailure(err, _) if err.code == ErrorCode::NotADatabase)
+}
+
+// an encrypted file looks like garbage to sqlite until the key is set
+pub fn is_encrypted() -> bool {
+    if !path().is_file() {
+        return false;
+    }
+
+    match Connection::open(path()).and_then(|db| db.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))) {
+        Err(err) => not_a_database(&err),
+        Ok(_) => false,
+    }
+}
+
+// false if the passphrase is wrong, the key is kept for every later open() if it is right
+pub fn unlock(passphrase: &str) -> Result<bool> {
+    if !cipher_available() {
+        return Err(eyre!("data.db is encrypted, but this signal-tui was built without the encryption feature"));
+    }
+
+    let db = Connection::open(path())?;
+    set_key(&db, passphrase)?;
+
+    match db.query_row("SELECT count(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0)) {
+        Ok(_) => {
+            *KEY.lock().unwrap() = Some(passphrase.to_string());
+            Ok(true)
+        }
+        Err(err) if not_a_database(&err) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// encrypts, re-keys or with None decrypts data.db. the caller has to close its own connections first
+pub fn change_passphrase(passphrase: Option<&str>) -> Result<()> {
+    if !cipher_available() {
+        return Err(eyre!("this signal-tui was built without the encryption feature, data.db can not be encrypted"));
+    }
+
+    // another signal-tui would go on writing to the replaced file and lose it, so ours lets go of its
+    // shared lock to see if anyone else holds one
+    let held = IN_USE.lock().unwrap().take().is_some();
+
+    let result = match lock_exclusive() {
+        Ok(Some(_lock)) => rekey(passphrase),
+        Ok(None) => Err(eyre!("signal-tui is running somewhere else, close it before changing the passphrase")),
+        Err(err) => Err(err),
+    };
+
+    if held {
+        mark_in_use()?;
+    }
+
+    result
+}
+
+fn rekey(passphrase: Option<&str>) -> Result<()> {
+    let target = path();
+    let rekeyed = target.with_extension("db.rekey");
+    let _ = std::fs::remove_file(&rekeyed);
+
+    // sqlcipher_export copies everything into the attached database, an empty key there means plaintext
+    {
+        let db = open()?;
+        db.execute(
+            "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+            rusqlite::params![rekeyed.to_string_lossy(), passphrase.unwrap_or_default()],
+        )?;
+        db.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()))?;
+        db.execute("DETACH DATABASE rekeyed", [])?;
+    }
+
+    std::fs::rename(&rekeyed, &target)
+        .map_err(|err| eyre!("Could not replace {}: {}", target.display(), err))?;
+
+    *KEY.lock().unwrap() = passphrase.map(|passphrase| passphrase.to_string());
+
+    Ok(())
+}
+
+// a consistent copy of data.db even while it is written to, encrypted with the same key
+pub fn snapshot(target: &Path) -> rusqlite::Result<()> {
+    open()?.execute("VACUUM INTO ?1", [target.to_string_lossy()])?;
+    Ok(())
+}
+
+// columns added after the first release, added to older databases on startup
+const MESSAGE_COLUMNS: &[(&str, &str)] = &[
+    ("quoteTimestamp", "INTEGER"),
+    ("quoteAuthor", "TEXT"),
+    ("quoteText", "TEXT"),
+    // json list of types::SignalAttachment
+    ("attachments", "TEXT"),
+];
+
 pub fn init(db: &rusqlite::Connection) -> rusqlite::Result<()> {
     db.execute(
         "CREATE TABLE IF NOT EXISTS messages (
@@ -16,5 +188,459 @@ pub fn init(db: &rusqlite::Connection) -> rusqlite::Result<()> {
         [],
     )?;
 
+    let existing: Vec<String> = db.prepare("SELECT name FROM pragma_table_info('messages')")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    for (column, kind) in MESSAGE_COLUMNS {
+        if !existing.iter().any(|name| name == column) {
+            db.execute(&format!("ALTER TABLE messages ADD COLUMN {} {}", column, kind), [])?;
+        }
+    }
+
+    // conversations are read by these when opened, and every incoming message is checked against them for duplicates
+    db.execute_batch(
+        "CREATE INDEX IF NOT EXISTS messagesByGroup ON messages (accountNumber, groupId, timestamp);
+        CREATE INDEX IF NOT EXISTS messagesByDestination ON messages (accountNumber, destinationUuid, timestamp);"
+    )?;
+
+    // one per person and message, messages are known by their timestamp.
+    // the primary key doubles as the index for looking up the reactions of a message
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS reactions (
+            accountNumber TEXT NOT NULL,
+            targetTimestamp INTEGER NOT NULL,
+            targetAuthorUuid TEXT,
+            sourceUuid TEXT NOT NULL,
+            sourceName TEXT NOT NULL,
+            emoji TEXT NOT NULL,
+            PRIMARY KEY (accountNumber, targetTimestamp, sourceUuid)
+        )",
+        [],
+    )?;
+
+    // conversationId is the group id for groups and the contact uuid for dms
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS lastRead (
+            accountNumber TEXT NOT NULL,
+            conversationId TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            PRIMARY KEY (accountNumber, conversationId)
+        )",
+        [],
+    )?;
+
+    // 0 = all, 1 = mentions only, 2 = muted, see notify::NotificationLevel
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS notificationLevels (
+            accountNumber TEXT NOT NULL,
+            conversationId TEXT NOT NULL,
+            level INTEGER NOT NULL,
+            PRIMARY KEY (accountNumber, conversationId)
+        )",
+        [],
+    )?;
+
+    db.execute(
+        "CREATE TABLE IF NOT EXISTS drafts (
+            accountNumber TEXT NOT NULL,
+            conversationId TEXT NOT NULL,
+            text TEXT NOT NULL,
+            PRIMARY KEY (accountNumber, conversationId)
+        )",
+        [],
+    )?;
+
     Ok(())
-}
\ No newline at end of file
+}
+
+// 0 = group, 1 = contact, same as everywhere else
+fn conversation_filter(conversation_type: usize) -> &'static str {
+    if conversation_type == 0 {
+        "groupId = ?1"
+    } else {
+        // same as destinationUuid = ?1 OR (sourceUuid = ?1 AND destinationUuid = 'self'), written so the index can be used.
+        // rows come back grouped by destination then, anything shown has to be ordered by timestamp
+        "destinationUuid IN (?1, 'self') AND (destinationUuid = ?1 OR sourceUuid = ?1)"
+    }
+}
+
+pub fn messages(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_type: usize,
+    conversation_id: &str,
+) -> rusqlite::Result<Vec<StoredMessage>> {
+    let mut query = db.prepare(&format!(
+        "SELECT sourceUuid, sourceName, sourceNumber, message, timestamp FROM messages WHERE {} and pending = 0 and accountNumber = ?2
+        ORDER BY timestamp, rowid",
+        conversation_filter(conversation_type)
+    ))?;
+
+    let rows = query.query_map([conversation_id, account_number], |row| {
+        Ok(StoredMessage {
+            source_uuid: row.get(0)?,
+            source_name: row.get(1)?,
+            source_number: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            message: row.get(3)?,
+            timestamp: row.get(4)?,
+        })
+    })?;
+
+    rows.collect()
+}
+
+// messages are known by their time and conversation, the text tells apart two sent in the same millisecond
+pub fn has_message(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_type: usize,
+    conversation_id: &str,
+    timestamp: u64,
+    message: &str,
+) -> rusqlite::Result<bool> {
+    let mut query = db.prepare(&format!(
+        "SELECT 1 FROM messages WHERE {} and accountNumber = ?2 and timestamp = ?3 and message = ?4 LIMIT 1",
+        conversation_filter(conversation_type)
+    ))?;
+
+    query.exists(rusqlite::params![conversation_id, account_number, timestamp, message])
+}
+
+// signal-cli confirmed a send, the timestamp it was sent with replaces the 0 it was stored with
+pub fn mark_sent(
+    db: &rusqlite::Connection,
+    id: &str,
+    timestamp: u64,
+) -> rusqlite::Result<()> {
+    db.execute(
+        "UPDATE messages SET pending = 0, timestamp = ?1 WHERE id = ?2",
+        rusqlite::params![timestamp, id]
+    )?;
+
+    Ok(())
+}
+
+pub fn last_read(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_id: &str,
+) -> rusqlite::Result<u64> {
+    let mut query = db.prepare(
+        "SELECT timestamp FROM lastRead WHERE accountNumber = ?1 and conversationId = ?2"
+    )?;
+    let mut rows = query.query([account_number, conversation_id])?;
+
+    match rows.next()? {
+        Some(row) => row.get(0),
+        None => Ok(0),
+    }
+}
+
+pub fn mark_read(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_id: &str,
+    timestamp: u64,
+) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT INTO lastRead (accountNumber, conversationId, timestamp) VALUES (?1, ?2, ?3)
+            ON CONFLICT (accountNumber, conversationId) DO UPDATE SET timestamp = MAX(timestamp, excluded.timestamp)",
+        rusqlite::params![account_number, conversation_id, timestamp],
+    )?;
+
+    Ok(())
+}
+
+// last message time + unread count for every conversation of an account, keyed by conversation id
+pub fn conversation_stats(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<HashMap<String, ConversationStats>> {
+    let mut query = db.prepare(
+        "SELECT m.conversationId, MAX(m.timestamp), SUM(
+            CASE WHEN m.timestamp > COALESCE(r.timestamp, 0)
+                and m.sourceUuid != 'self'
+                and (m.sourceNumber IS NULL or m.sourceNumber != ?1)
+            THEN 1 ELSE 0 END
+        )
+        FROM (
+            SELECT COALESCE(groupId, CASE WHEN destinationUuid = 'self' THEN sourceUuid ELSE destinationUuid END) AS conversationId,
+                timestamp, sourceUuid, sourceNumber
+            FROM messages WHERE accountNumber = ?1 and pending = 0
+        ) m
+        LEFT JOIN lastRead r ON r.accountNumber = ?1 and r.conversationId = m.conversationId
+        WHERE m.conversationId IS NOT NULL
+        GROUP BY m.conversationId"
+    )?;
+
+    let rows = query.query_map([account_number], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            ConversationStats {
+                last_message: row.get(1)?,
+                unread: row.get(2)?,
+            }
+        ))
+    })?;
+
+    rows.collect()
+}
+
+pub fn notification_levels(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<HashMap<String, NotificationLevel>> {
+    let mut query = db.prepare(
+        "SELECT conversationId, level FROM notificationLevels WHERE accountNumber = ?1"
+    )?;
+
+    let rows = query.query_map([account_number], |row| {
+        Ok((row.get::<_, String>(0)?, NotificationLevel::from_i64(row.get(1)?)))
+    })?;
+
+    rows.collect()
+}
+
+pub fn set_notification_level(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_id: &str,
+    level: NotificationLevel,
+) -> rusqlite::Result<()> {
+    db.execute(
+        "INSERT INTO notificationLevels (accountNumber, conversationId, level) VALUES (?1, ?2, ?3)
+            ON CONFLICT (accountNumber, conversationId) DO UPDATE SET level = excluded.level",
+        rusqlite::params![account_number, conversation_id, level.to_i64()],
+    )?;
+
+    Ok(())
+}
+
+// signal-cli only tells us our number, but our uuid shows up as the source of sync messages from our other devices
+pub fn own_uuid(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<Option<String>> {
+    let mut query = db.prepare(
+        "SELECT sourceUuid FROM messages WHERE accountNumber = ?1 and sourceNumber = ?1 and sourceUuid != 'self' LIMIT 1"
+    )?;
+    let mut rows = query.query([account_number])?;
+
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn drafts(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<HashMap<String, String>> {
+    let mut query = db.prepare(
+        "SELECT conversationId, text FROM drafts WHERE accountNumber = ?1"
+    )?;
+
+    let rows = query.query_map([account_number], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    rows.collect()
+}
+
+// an empty draft removes it
+pub fn save_draft(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_id: &str,
+    text: &str,
+) -> rusqlite::Result<()> {
+    if text.is_empty() {
+        db.execute(
+            "DELETE FROM drafts WHERE accountNumber = ?1 and conversationId = ?2",
+            [account_number, conversation_id],
+        )?;
+    } else {
+        db.execute(
+            "INSERT INTO drafts (accountNumber, conversationId, text) VALUES (?1, ?2, ?3)
+                ON CONFLICT (accountNumber, conversationId) DO UPDATE SET text = excluded.text",
+            [account_number, conversation_id, text],
+        )?;
+    }
+
+    Ok(())
+}
+
+// newest message of the account, None if it has none yet
+pub fn last_activity(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<Option<u64>> {
+    db.query_row(
+        "SELECT MAX(timestamp) FROM messages WHERE accountNumber = ?1 and pending = 0",
+        [account_number],
+        |row| row.get(0),
+    )
+}
+
+// our name as our other devices send it, sync messages carry it as the source name
+pub fn own_name(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<Option<String>> {
+    let mut query = db.prepare(
+        "SELECT sourceName FROM messages WHERE accountNumber = ?1 and sourceNumber = ?1 and sourceUuid != 'self' and sourceName != '' ORDER BY timestamp DESC LIMIT 1"
+    )?;
+    let mut rows = query.query([account_number])?;
+
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+// everything stored for an account, when it gets removed
+pub fn purge_account(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<()> {
+    for table in ["messages", "lastRead", "notificationLevels", "drafts", "reactions"] {
+        db.execute(&format!("DELETE FROM {} WHERE accountNumber = ?1", table), [account_number])?;
+    }
+
+    Ok(())
+}
+
+// a new reaction replaces the one the person had on the message
+pub fn save_reaction(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    reaction: &types::SignalReaction,
+    source_uuid: &str,
+    source_name: &str,
+) -> rusqlite::Result<()> {
+    if reaction.is_remove {
+        db.execute(
+            "DELETE FROM reactions WHERE accountNumber = ?1 and targetTimestamp = ?2 and sourceUuid = ?3",
+            rusqlite::params![account_number, reaction.target_sent_timestamp, source_uuid],
+        )?;
+    } else {
+        db.execute(
+            "INSERT INTO reactions (accountNumber, targetTimestamp, targetAuthorUuid, sourceUuid, sourceName, emoji) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT (accountNumber, targetTimestamp, sourceUuid) DO UPDATE SET emoji = excluded.emoji, sourceName = excluded.sourceName",
+            rusqlite::params![account_number, reaction.target_sent_timestamp, reaction.target_author_uuid, source_uuid, source_name, reaction.emoji],
+        )?;
+    }
+
+    Ok(())
+}
+
+// every conversation with stored messages as (0 = group / 1 = contact, id)
+pub fn conversations(
+    db: &rusqlite::Connection,
+    account_number: &str,
+) -> rusqlite::Result<Vec<(usize, String)>> {
+    let mut query = db.prepare(
+        "SELECT DISTINCT CASE WHEN groupId IS NULL THEN 1 ELSE 0 END,
+            COALESCE(groupId, CASE WHEN destinationUuid = 'self' THEN sourceUuid ELSE destinationUuid END)
+        FROM messages WHERE accountNumber = ?1 and pending = 0"
+    )?;
+
+    let rows = query.query_map([account_number], |row| Ok((row.get(0)?, row.get::<_, Option<String>>(1)?)))?;
+
+    Ok(rows
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .into_iter()
+        .filter_map(|(kind, id)| Some((kind, id?)))
+        .collect())
+}
+
+// messages with their quotes, reactions and attachments, from and to are inclusive timestamps
+pub fn archived_messages(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    conversation_type: usize,
+    conversation_id: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> rusqlite::Result<Vec<types::ArchivedMessage>> {
+    let mut query = db.prepare(&format!(
+        "SELECT id, timestamp, sourceUuid, sourceNumber, sourceName, message, expiresInSeconds, quoteTimestamp, quoteAuthor, quoteText, attachments
+        FROM messages WHERE {} and pending = 0 and accountNumber = ?2 and timestamp >= ?3 and timestamp <= ?4
+        ORDER BY timestamp, rowid",
+        conversation_filter(conversation_type)
+    ))?;
+
+    let rows = query.query_map(
+        rusqlite::params![conversation_id, account_number, from.unwrap_or(0) as i64, to.map(|to| to as i64).unwrap_or(i64::MAX)],
+        |row| {
+            let quote_timestamp: Option<u64> = row.get(7)?;
+            let attachments: Option<String> = row.get(10)?;
+
+            Ok(types::ArchivedMessage {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                source_uuid: row.get(2)?,
+                source_number: row.get(3)?,
+                source_name: row.get(4)?,
+                message: row.get(5)?,
+                expires_in_seconds: row.get::<_, Option<u64>>(6)?.unwrap_or(0),
+                quote: quote_timestamp.map(|timestamp| Ok::<_, rusqlite::Error>(types::ArchivedQuote {
+                    timestamp,
+                    author: row.get(8)?,
+                    text: row.get(9)?,
+                })).transpose()?,
+                reactions: vec![],
+                attachments: attachments.and_then(|json| serde_json::from_str(&json).ok()).unwrap_or_default(),
+            })
+        }
+    )?;
+
+    let mut messages = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut reactions = db.prepare(
+        "SELECT emoji, sourceUuid, sourceName FROM reactions WHERE accountNumber = ?1 and targetTimestamp = ?2"
+    )?;
+
+    for message in &mut messages {
+        message.reactions = reactions
+            .query_map(rusqlite::params![account_number, message.timestamp], |row| Ok(types::ArchivedReaction {
+                emoji: row.get(0)?,
+                source_uuid: row.get(1)?,
+                source_name: row.get(2)?,
+            }))?
+            .collect::<rusqlite::Result<_>>()?;
+    }
+
+    Ok(messages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direct_messages_in_time_order() {
+        let db = Connection::open_in_memory().unwrap();
+        init(&db).unwrap();
+
+        // id, source, destination, text, timestamp; sent and received alternate
+        let rows = [
+            ("1", "self", "u-1", "hi", 1),
+            ("2", "u-1", "self", "hey", 2),
+            ("3", "self", "u-1", "how are you", 3),
+            ("4", "u-1", "self", "good", 4),
+            ("5", "u-2", "self", "someone else", 5),
+        ];
+
+        for (id, source, destination, text, timestamp) in rows {
+            db.execute(
+                "INSERT INTO messages (id, sourceUuid, sourceName, destinationUuid, message, timestamp, pending, accountNumber) VALUES (?1, ?2, '', ?3, ?4, ?5, 0, '+1')",
+                rusqlite::params![id, source, destination, text, timestamp],
+            ).unwrap();
+        }
+
+        let texts: Vec<String> = messages(&db, "+1", 1, "u-1").unwrap().into_iter().map(|m| m.message).collect();
+        assert_eq!(texts, ["hi", "hey", "how are you", "good"]);
+    }
+}
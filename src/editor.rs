@@ -0,0 +1,370 @@
+use std::io::Write;
+
+use unicode_width::UnicodeWidthChar;
+
+// the message composer, cursor is a byte index that always sits on a char boundary
+#[derive(Default)]
+pub struct Editor {
+    text: String,
+    cursor: usize,
+    // previously sent messages, oldest first
+    history: Vec<String>,
+    history_index: Option<usize>,
+    // what was typed before browsing the history, restored when going past the newest entry
+    stash: String,
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Editor::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+        self.history_index = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text("");
+    }
+
+    // remembers a sent message for history recall
+    pub fn push_history(&mut self, text: &str) {
+        if !text.is_empty() && self.history.last().map(|last| last.as_str()) != Some(text) {
+            self.history.push(text.to_string());
+        }
+        self.history_index = None;
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn insert_str(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    pub fn insert_newline(&mut self) {
+        self.insert_char('\n');
+    }
+
+    fn prev_boundary(&self, index: usize) -> usize {
+        self.text[..index].char_indices().next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, index: usize) -> usize {
+        self.text[index..].chars().next().map(|c| index + c.len_utf8()).unwrap_or(index)
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor].rfind('\n').map(|i| i + 1).unwrap_or(0)
+    }
+
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..].find('\n').map(|i| self.cursor + i).unwrap_or(self.text.len())
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.prev_boundary(self.cursor);
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = self.next_boundary(self.cursor);
+    }
+
+    pub fn move_line_start(&mut self) {
+        self.cursor = self.line_start();
+    }
+
+    pub fn move_line_end(&mut self) {
+        self.cursor = self.line_end();
+    }
+
+    // start of the previous word, skipping whitespace first like most shells
+    fn word_start_before(&self, index: usize) -> usize {
+        let before = &self.text[..index];
+        let trimmed = before.trim_end_matches(|c: char| !c.is_alphanumeric());
+        trimmed.rfind(|c: char| !c.is_alphanumeric())
+            .map(|i| i + trimmed[i..].chars().next().unwrap().len_utf8())
+            .unwrap_or(0)
+    }
+
+    fn word_end_after(&self, index: usize) -> usize {
+        let after = &self.text[index..];
+        let skipped = after.len() - after.trim_start_matches(|c: char| !c.is_alphanumeric()).len();
+        let rest = &after[skipped..];
+        index + skipped + rest.find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len())
+    }
+
+    pub fn move_word_left(&mut self) {
+        self.cursor = self.word_start_before(self.cursor);
+    }
+
+    pub fn move_word_right(&mut self) {
+        self.cursor = self.word_end_after(self.cursor);
+    }
+
+    // returns false when already on the first line, so the caller can fall back to history
+    pub fn move_up(&mut self) -> bool {
+        let start = self.line_start();
+        if start == 0 {
+            return false;
+        }
+
+        let column = self.text[start..self.cursor].chars().count();
+        let prev_start = self.text[..start - 1].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        self.cursor = self.column_index(prev_start, start - 1, column);
+
+        true
+    }
+
+    pub fn move_down(&mut self) -> bool {
+        let end = self.line_end();
+        if end == self.text.len() {
+            return false;
+        }
+
+        let column = self.text[self.line_start()..self.cursor].chars().count();
+        let next_start = end + 1;
+        let next_end = self.text[next_start..].find('\n').map(|i| next_start + i).unwrap_or(self.text.len());
+        self.cursor = self.column_index(next_start, next_end, column);
+
+        true
+    }
+
+    fn column_index(&self, line_start: usize, line_end: usize, column: usize) -> usize {
+        self.text[line_start..line_end].char_indices()
+            .nth(column)
+            .map(|(i, _)| line_start + i)
+            .unwrap_or(line_end)
+    }
+
+    pub fn delete_backward(&mut self) {
+        let prev = self.prev_boundary(self.cursor);
+        self.text.replace_range(prev..self.cursor, "");
+        self.cursor = prev;
+    }
+
+    pub fn delete_forward(&mut self) {
+        let next = self.next_boundary(self.cursor);
+        self.text.replace_range(self.cursor..next, "");
+    }
+
+    pub fn delete_word_backward(&mut self) {
+        let start = self.word_start_before(self.cursor);
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn delete_to_line_start(&mut self) {
+        let start = self.line_start();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn history_previous(&mut self) {
+        let index = match self.history_index {
+            Some(0) => return,
+            Some(i) => i - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.stash = self.text.clone();
+                self.history.len() - 1
+            }
+        };
+
+        self.history_index = Some(index);
+        self.text = self.history[index].clone();
+        self.cursor = self.text.len();
+    }
+
+    pub fn history_next(&mut self) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+
+        if index + 1 < self.history.len() {
+            self.history_index = Some(index + 1);
+            self.text = self.history[index + 1].clone();
+        } else {
+            self.history_index = None;
+            self.text = std::mem::take(&mut self.stash);
+        }
+        self.cursor = self.text.len();
+    }
+
+    // the text wrapped to width by display width, and the row/column of the cursor in it
+    pub fn layout(&self, width: usize) -> (Vec<String>, (usize, usize)) {
+        let width = width.max(1);
+        let mut rows = vec![String::new()];
+        let mut row_width = 0;
+        let mut cursor = (0, 0);
+
+        for (i, c) in self.text.char_indices() {
+            if i == self.cursor {
+                cursor = (rows.len() - 1, row_width);
+            }
+
+            if c == '\n' {
+                rows.push(String::new());
+                row_width = 0;
+                continue;
+            }
+
+            let char_width = c.width().unwrap_or(0);
+            if row_width + char_width > width {
+                rows.push(String::new());
+                row_width = 0;
+
+                if i == self.cursor {
+                    cursor = (rows.len() - 1, 0);
+                }
+            }
+
+            rows.last_mut().unwrap().push(c);
+            row_width += char_width;
+        }
+
+        if self.cursor == self.text.len() {
+            // a full row puts the cursor at the start of the next one
+            if row_width >= width {
+                rows.push(String::new());
+                row_width = 0;
+            }
+            cursor = (rows.len() - 1, row_width);
+        }
+
+        (rows, cursor)
+    }
+}
+
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+// opens $VISUAL/$EDITOR on a temp file with the draft, everything from the scissors line down is dropped
+pub fn compose_external(draft: &str, quote: Option<&str>) -> std::io::Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| match std::env::consts::OS {
+            "windows" => "notepad".to_string(),
+            _ => "vi".to_string(),
+        });
+
+    let path = std::env::temp_dir().join(format!("signal-tui-{}.txt", crate::signal::generate_id()));
+
+    let mut content = draft.to_string();
+    if let Some(quote) = quote {
+        content.push_str(&format!("\n\n{}\n# Everything below this line is ignored.\n#\n", SCISSORS));
+        for line in quote.lines() {
+            content.push_str(&format!("# > {}\n", line));
+        }
+    }
+
+    // the temp dir is shared, only we get to read the draft and a file someone put there first is not followed
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&path)?;
+
+    let result = (|| {
+        file.write_all(content.as_bytes())?;
+        drop(file);
+
+        // $EDITOR can have arguments, eg. "code --wait"
+        let mut parts = editor.split_whitespace();
+        let program = parts.next().unwrap_or("vi");
+
+        let status = std::process::Command::new(program)
+            .args(parts)
+            .arg(&path)
+            .status()?;
+
+        if !status.success() {
+            return Err(std::io::Error::other(format!("{} exited with {}", editor, status)));
+        }
+
+        std::fs::read_to_string(&path)
+    })();
+
+    // whatever happened above, the draft does not stay behind
+    let _ = std::fs::remove_file(&path);
+
+    let text = result?;
+    let text = match text.find(SCISSORS) {
+        Some(i) => &text[..i],
+        None => &text,
+    };
+
+    Ok(text.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Step = (fn(&mut Editor), &'static str);
+    // text, cursor byte index, width, rows, cursor row and column
+    type WrapCase = (&'static str, usize, usize, &'static [&'static str], (usize, usize));
+
+    // the text with a | where the cursor is
+    fn marked(editor: &Editor) -> String {
+        format!("{}|{}", &editor.text[..editor.cursor], &editor.text[editor.cursor..])
+    }
+
+    #[test]
+    fn multi_byte_cursor() {
+        let mut editor = Editor::new();
+        editor.set_text("aé😀b\nxyz");
+
+        // each step goes on from where the one before left the cursor
+        let steps: [Step; 11] = [
+            (Editor::move_left, "aé😀b\nxy|z"),
+            (|e| assert!(e.move_up()), "aé|😀b\nxyz"),
+            (Editor::move_right, "aé😀|b\nxyz"),
+            (|e| assert!(e.move_down()), "aé😀b\nxyz|"),
+            (Editor::move_line_start, "aé😀b\n|xyz"),
+            (Editor::move_left, "aé😀b|\nxyz"),
+            (Editor::delete_backward, "aé😀|\nxyz"),
+            (Editor::delete_backward, "aé|\nxyz"),
+            (Editor::move_line_start, "|aé\nxyz"),
+            (Editor::delete_forward, "|é\nxyz"),
+            (Editor::move_right, "é|\nxyz"),
+        ];
+
+        for (i, (step, expected)) in steps.into_iter().enumerate() {
+            step(&mut editor);
+            assert_eq!(marked(&editor), expected, "step {}", i);
+        }
+    }
+
+    #[test]
+    fn wrap_at_width() {
+        let cases: [WrapCase; 7] = [
+            ("hello world", 11, 5, &["hello", " worl", "d"], (2, 1)),
+            ("abcde", 5, 5, &["abcde", ""], (1, 0)),
+            ("abcdef", 3, 3, &["abc", "def"], (1, 0)),
+            ("ab\ncd", 5, 10, &["ab", "cd"], (1, 2)),
+            ("ab\ncd", 2, 10, &["ab", "cd"], (0, 2)),
+            // wide chars take two columns and dont get split
+            ("你好吗", 9, 4, &["你好", "吗"], (1, 2)),
+            ("ab", 2, 0, &["a", "b", ""], (2, 0)),
+        ];
+
+        for (text, cursor, width, rows, expected_cursor) in cases {
+            let mut editor = Editor::new();
+            editor.set_text(text);
+            editor.cursor = cursor;
+
+            let (layout_rows, layout_cursor) = editor.layout(width);
+            assert_eq!(layout_rows, rows, "{:?} at width {}", text, width);
+            assert_eq!(layout_cursor, expected_cursor, "{:?} at width {}", text, width);
+        }
+    }
+}
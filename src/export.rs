@@ -0,0 +1,474 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::{eyre::eyre, Result};
+use ratatui::DefaultTerminal;
+use rusqlite::Connection;
+
+use crate::{app, commands, db, types, ui};
+
+// of the json export, the importer refuses newer ones
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    Markdown,
+    Html,
+    Text,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "md" | "markdown" => Some(Format::Markdown),
+            "html" | "htm" => Some(Format::Html),
+            "txt" | "text" => Some(Format::Text),
+            _ => None,
+        }
+    }
+
+    // picked by the extension of the file being written
+    pub fn from_path(path: &Path) -> Option<Format> {
+        Format::parse(&path.extension()?.to_string_lossy())
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Markdown => "md",
+            Format::Html => "html",
+            Format::Text => "txt",
+        }
+    }
+}
+
+pub struct Conversation {
+    // 0 = group, 1 = contact
+    pub kind: usize,
+    pub id: String,
+    pub name: String,
+}
+
+// every conversation of the account with stored messages, named like the sidebar does
+pub fn conversations(
+    db: &Connection,
+    account_number: &str,
+    groups: &[types::SignalGroup],
+    contacts: &[types::SignalContact],
+) -> Result<Vec<Conversation>> {
+    let conversations = db::conversations(db, account_number)?
+        .into_iter()
+        .map(|(kind, id)| {
+            let name = if kind == 0 {
+                groups.iter().find(|group| group.id == id).map(|group| group.name.clone())
+            } else {
+                contacts.iter().find(|contact| contact.uuid == id).map(app::contact_name)
+            };
+
+            Conversation { kind, name: name.filter(|name| !name.is_empty()).unwrap_or(id.clone()), id }
+        })
+        .collect();
+
+    Ok(conversations)
+}
+
+// YYYY-MM-DD in local time, end picks the last millisecond of the day so ranges include both days
+pub fn parse_date(date: &str, end: bool) -> Result<u64> {
+    let day = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| eyre!("\"{}\" is not a date, use YYYY-MM-DD", date))?;
+
+    let time = if end {
+        day.and_hms_milli_opt(23, 59, 59, 999)
+    } else {
+        day.and_hms_opt(0, 0, 0)
+    }.ok_or_else(|| eyre!("\"{}\" is not a date", date))?;
+
+    let local = time.and_local_timezone(chrono::Local)
+        .earliest()
+        .ok_or_else(|| eyre!("{} does not exist in the local timezone", date))?;
+
+    Ok(local.timestamp_millis().max(0) as u64)
+}
+
+// something that works as a file name everywhere
+pub fn file_name(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' { c } else { '_' })
+        .collect();
+
+    let name = name.trim_matches(['.', '_']);
+    if name.is_empty() { "conversation".to_string() } else { name.to_string() }
+}
+
+// data_dir/exports/<account>-<name>-<time>.<ext>, or a directory of them without a name
+pub fn default_path(account_number: &str, name: Option<&str>, format: Format) -> PathBuf {
+    let time = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let dir = crate::config::get().data_dir().join("exports");
+
+    match name {
+        Some(name) => dir.join(format!("{}-{}-{}.{}", file_name(account_number), file_name(name), time, format.extension())),
+        None => dir.join(format!("{}-{}", file_name(account_number), time)),
+    }
+}
+
+// writes one conversation to path, attachments go into <name>-attachments next to it. returns the number of messages
+pub fn export_conversation(
+    db: &Connection,
+    account_number: &str,
+    conversation: &Conversation,
+    format: Format,
+    path: &Path,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<usize> {
+    let mut messages = db::archived_messages(db, account_number, conversation.kind, &conversation.id, from, to)?;
+
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    copy_attachments(&mut messages, path)?;
+
+    let output = match format {
+        Format::Json => json(account_number, conversation, &messages)?,
+        Format::Markdown => markdown(account_number, conversation, &messages),
+        Format::Html => html(account_number, conversation, &messages),
+        Format::Text => text(account_number, conversation, &messages),
+    };
+
+    std::fs::write(path, output)
+        .map_err(|err| eyre!("Could not write {}: {}", path.display(), err))?;
+
+    Ok(messages.len())
+}
+
+// one file per conversation in dir, conversations without messages in the range are left out. returns (files, messages)
+pub fn export_all(
+    db: &Connection,
+    account_number: &str,
+    conversations: &[Conversation],
+    format: Format,
+    dir: &Path,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<(usize, usize)> {
+    std::fs::create_dir_all(dir)
+        .map_err(|err| eyre!("Could not create {}: {}", dir.display(), err))?;
+
+    let mut used: Vec<String> = vec![];
+    let (mut files, mut total) = (0, 0);
+
+    for conversation in conversations {
+        if db::archived_messages(db, account_number, conversation.kind, &conversation.id, from, to)?.is_empty() {
+            continue;
+        }
+
+        // two contacts can have the same name
+        let mut stem = file_name(&conversation.name);
+        if used.contains(&stem) {
+            stem = format!("{}-{}", stem, file_name(&conversation.id));
+        }
+        used.push(stem.clone());
+
+        let path = dir.join(format!("{}.{}", stem, format.extension()));
+        total += export_conversation(db, account_number, conversation, format, &path, from, to)?;
+        files += 1;
+    }
+
+    Ok((files, total))
+}
+
+// asks for the format, where to write and the dates, then exports one conversation or all of them.
+// returns what the status line should say, None if it was cancelled
+pub fn interactive(
+    terminal: &mut DefaultTerminal,
+    db: &Connection,
+    account_number: &str,
+    conversations: &[Conversation],
+    single: Option<&Conversation>,
+) -> Result<Option<String>> {
+    let what = match single {
+        Some(conversation) => conversation.name.clone(),
+        None => format!("every conversation of {}", account_number),
+    };
+
+    let message = format!("Export {}.\n\nFormat: json, md, html or txt. Empty for md.", what);
+    let format = loop {
+        let Some(name) = ui::prompt(terminal, "Export", &message, false)? else {
+            return Ok(None);
+        };
+
+        if name.is_empty() {
+            break Format::Markdown;
+        }
+        if let Some(format) = Format::parse(&name) {
+            break format;
+        }
+    };
+
+    let default = default_path(account_number, single.map(|conversation| conversation.name.as_str()), format);
+    let message = format!(
+        "Where to write it{}.\n\nEmpty for {}",
+        if single.is_some() { "" } else { ", a directory with one file per conversation" },
+        default.display()
+    );
+    let Some(path) = ui::prompt(terminal, "Export", &message, false)? else {
+        return Ok(None);
+    };
+    let path = if path.is_empty() { default } else { commands::expand_home(&path) };
+
+    let mut message = "Only messages between two dates, eg. 2024-01-01 2024-06-30.\nOne date exports from then on, empty exports everything.".to_string();
+    let (from, to) = loop {
+        let Some(range) = ui::prompt(terminal, "Export", &message, false)? else {
+            return Ok(None);
+        };
+
+        let dates: Vec<&str> = range.split_whitespace().collect();
+        let parsed = match dates.as_slice() {
+            [] => Ok((None, None)),
+            [from] => parse_date(from, false).map(|from| (Some(from), None)),
+            [from, to] => parse_date(from, false).and_then(|from| Ok((Some(from), Some(parse_date(to, true)?)))),
+            _ => Err(eyre!("Two dates at most")),
+        };
+
+        match parsed {
+            Ok(range) => break range,
+            Err(err) => message = format!("{}\n\nOnly messages between two dates, eg. 2024-01-01 2024-06-30.", err),
+        }
+    };
+
+    crate::install::draw_message(terminal, "Export", &format!("Exporting {}...", what))?;
+
+    let result = match single {
+        Some(conversation) => export_conversation(db, account_number, conversation, format, &path, from, to)
+            .map(|messages| format!("Exported {} messages to {}", messages, path.display())),
+        None => export_all(db, account_number, conversations, format, &path, from, to)
+            .map(|(files, messages)| format!("Exported {} messages in {} conversations to {}", messages, files, path.display())),
+    };
+
+    Ok(Some(result.unwrap_or_else(|err| format!("Export failed: {}", err))))
+}
+
+// signal-cli keeps received files under their id, what we sent is wherever it was picked from
+fn attachment_source(attachment: &types::SignalAttachment) -> Option<PathBuf> {
+    if let Some(path) = attachment.path.as_ref().map(PathBuf::from).filter(|path| path.is_file()) {
+        return Some(path);
+    }
+
+    if attachment.id.is_empty() {
+        return None;
+    }
+
+    let path = crate::config::get().signal_cli_data_dir().join("attachments").join(&attachment.id);
+    path.is_file().then_some(path)
+}
+
+fn attachment_name(attachment: &types::SignalAttachment) -> String {
+    attachment.filename.clone()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| if attachment.id.is_empty() { "attachment".to_string() } else { attachment.id.clone() })
+}
+
+// copies what still exists and points path at the copy, relative to the export. missing files end up without a path
+fn copy_attachments(messages: &mut [types::ArchivedMessage], output: &Path) -> Result<()> {
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let dir_name = format!("{}-attachments", stem);
+    let dir = output.with_file_name(&dir_name);
+
+    for message in messages {
+        for attachment in &mut message.attachments {
+            let Some(source) = attachment_source(attachment) else {
+                attachment.path = None;
+                continue;
+            };
+
+            std::fs::create_dir_all(&dir)?;
+
+            let name = format!("{}-{}", message.timestamp, file_name(&attachment_name(attachment)));
+            std::fs::copy(&source, dir.join(&name))
+                .map_err(|err| eyre!("Could not copy {}: {}", source.display(), err))?;
+
+            attachment.path = Some(format!("{}/{}", dir_name, name));
+        }
+    }
+
+    Ok(())
+}
+
+fn kind_name(kind: usize) -> &'static str {
+    if kind == 0 { "group" } else { "contact" }
+}
+
+fn time(timestamp: u64) -> String {
+    app::format_timestamp(timestamp, "%Y-%m-%d %H:%M")
+}
+
+fn exported_line(account_number: &str, messages: &[types::ArchivedMessage]) -> String {
+    format!(
+        "Exported from {} on {}, {} messages",
+        account_number,
+        chrono::Local::now().format("%Y-%m-%d %H:%M"),
+        messages.len()
+    )
+}
+
+fn quote_author(quote: &types::ArchivedQuote) -> &str {
+    quote.author.as_deref().unwrap_or("unknown")
+}
+
+fn reactions_line(reactions: &[types::ArchivedReaction]) -> String {
+    reactions.iter()
+        .map(|reaction| format!("{} {}", reaction.emoji, reaction.source_name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn json(account_number: &str, conversation: &Conversation, messages: &[types::ArchivedMessage]) -> Result<String> {
+    let export = types::ArchivedConversation {
+        version: VERSION,
+        account: account_number.to_string(),
+        conversation: types::ArchivedConversationInfo {
+            id: conversation.id.clone(),
+            kind: kind_name(conversation.kind).to_string(),
+            name: conversation.name.clone(),
+        },
+        exported: chrono::Local::now().timestamp_millis(),
+        messages: messages.to_vec(),
+    };
+
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+fn text(account_number: &str, conversation: &Conversation, messages: &[types::ArchivedMessage]) -> String {
+    let mut out = format!("{}\n{}\n\n", conversation.name, exported_line(account_number, messages));
+
+    for message in messages {
+        // continuation lines are indented so every message starts at the margin
+        out.push_str(&format!("[{}] {}: {}\n", time(message.timestamp), message.source_name, message.message.replace('\n', "\n    ")));
+
+        if let Some(quote) = &message.quote {
+            out.push_str(&format!("    > {}: {}\n", quote_author(quote), quote.text.as_deref().unwrap_or_default().replace('\n', " ")));
+        }
+
+        for attachment in &message.attachments {
+            out.push_str(&match &attachment.path {
+                Some(path) => format!("    [attachment: {} -> {}]\n", attachment_name(attachment), path),
+                None => format!("    [attachment: {} (missing)]\n", attachment_name(attachment)),
+            });
+        }
+
+        if !message.reactions.is_empty() {
+            out.push_str(&format!("    reactions: {}\n", reactions_line(&message.reactions)));
+        }
+    }
+
+    out
+}
+
+fn markdown(account_number: &str, conversation: &Conversation, messages: &[types::ArchivedMessage]) -> String {
+    let mut out = format!("# {}\n\n_{}_\n", conversation.name, exported_line(account_number, messages));
+
+    for message in messages {
+        out.push_str(&format!("\n---\n\n**{}** · {}\n\n", message.source_name, time(message.timestamp)));
+
+        if let Some(quote) = &message.quote {
+            let text = quote.text.as_deref().unwrap_or_default().replace('\n', "\n> ");
+            out.push_str(&format!("> **{}**: {}\n\n", quote_author(quote), text));
+        }
+
+        if !message.message.is_empty() {
+            // a trailing double space keeps single line breaks
+            out.push_str(&format!("{}\n\n", message.message.replace('\n', "  \n")));
+        }
+
+        for attachment in &message.attachments {
+            let name = attachment_name(attachment);
+
+            out.push_str(&match &attachment.path {
+                Some(path) if is_image(attachment) => format!("![{}](<{}>)\n\n", name, path),
+                Some(path) => format!("- [{}](<{}>)\n\n", name, path),
+                None => format!("- {} (missing)\n\n", name),
+            });
+        }
+
+        if !message.reactions.is_empty() {
+            out.push_str(&format!("{}\n", reactions_line(&message.reactions)));
+        }
+    }
+
+    out
+}
+
+fn is_image(attachment: &types::SignalAttachment) -> bool {
+    attachment.content_type.as_deref().is_some_and(|kind| kind.starts_with("image/"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; max-width: 48em; margin: 2em auto; padding: 0 1em; color: #222; }
+.meta { color: #777; font-size: 0.9em; }
+.message { margin: 1em 0; padding: 0.5em 0.8em; border-radius: 0.5em; background: #f1f1f4; }
+.message.own { background: #dde9ff; }
+.text { white-space: pre-wrap; margin: 0.3em 0; }
+.quote { border-left: 3px solid #999; padding-left: 0.6em; color: #555; margin: 0.3em 0; white-space: pre-wrap; }
+.reactions { font-size: 0.9em; color: #555; }
+img { max-width: 100%; max-height: 30em; display: block; margin: 0.3em 0; }";
+
+// one file that opens in any browser, attachments are linked from their folder next to it
+fn html(account_number: &str, conversation: &Conversation, messages: &[types::ArchivedMessage]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>{}</h1>\n<p class=\"meta\">{}</p>\n",
+        escape(&conversation.name),
+        HTML_STYLE,
+        escape(&conversation.name),
+        escape(&exported_line(account_number, messages))
+    );
+
+    for message in messages {
+        let own = message.source_uuid == "self" || message.source_number.as_deref() == Some(account_number);
+
+        out.push_str(&format!(
+            "<div class=\"message{}\">\n<div class=\"meta\"><b>{}</b> · {}</div>\n",
+            if own { " own" } else { "" },
+            escape(&message.source_name),
+            time(message.timestamp)
+        ));
+
+        if let Some(quote) = &message.quote {
+            out.push_str(&format!(
+                "<div class=\"quote\"><b>{}</b>: {}</div>\n",
+                escape(quote_author(quote)),
+                escape(quote.text.as_deref().unwrap_or_default())
+            ));
+        }
+
+        if !message.message.is_empty() {
+            out.push_str(&format!("<div class=\"text\">{}</div>\n", escape(&message.message)));
+        }
+
+        for attachment in &message.attachments {
+            let name = escape(&attachment_name(attachment));
+
+            out.push_str(&match &attachment.path {
+                Some(path) if is_image(attachment) => format!("<a href=\"{0}\"><img src=\"{0}\" alt=\"{1}\"></a>\n", escape(path), name),
+                Some(path) => format!("<div><a href=\"{}\">{}</a></div>\n", escape(path), name),
+                None => format!("<div class=\"meta\">{} (missing)</div>\n", name),
+            });
+        }
+
+        if !message.reactions.is_empty() {
+            out.push_str(&format!("<div class=\"reactions\">{}</div>\n", escape(&reactions_line(&message.reactions))));
+        }
+
+        out.push_str("</div>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
@@ -0,0 +1,72 @@
+// subsequence matching like most fuzzy finders, None if the query chars dont all appear in order
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let query: Vec<char> = query.to_lowercase().chars().filter(|c| !c.is_whitespace()).collect();
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let original: Vec<char> = candidate.chars().collect();
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+
+    // lowercasing can change the length for a few chars, word starts are only a bonus so just skip them then
+    let same_length = original.len() == candidate.len();
+
+    let mut score = 0;
+    let mut query_index = 0;
+    let mut previous_match: Option<usize> = None;
+
+    for (i, c) in candidate.iter().enumerate() {
+        if query_index == query.len() {
+            break;
+        }
+
+        if *c != query[query_index] {
+            continue;
+        }
+
+        score += 1;
+
+        if previous_match.is_some_and(|previous| previous + 1 == i) {
+            score += 5;
+        } else if let Some(previous) = previous_match {
+            // gaps between matched chars make it a worse match
+            score -= (i - previous - 1).min(5) as i64;
+        }
+
+        let word_start = i == 0 || (same_length && {
+            let before = original[i - 1];
+            !before.is_alphanumeric() || (before.is_lowercase() && original[i].is_uppercase())
+        });
+        if word_start {
+            score += 8;
+        }
+
+        previous_match = Some(i);
+        query_index += 1;
+    }
+
+    if query_index < query.len() {
+        return None;
+    }
+
+    // exact names and prefixes beat everything else
+    let query: String = query.into_iter().collect();
+    let candidate: String = candidate.into_iter().collect();
+    if candidate == query {
+        score += 100;
+    } else if candidate.starts_with(&query) {
+        score += 50;
+    } else if candidate.contains(&query) {
+        score += 20;
+    }
+
+    Some(score)
+}
+
+// best score over all the names something is known by
+pub fn best_score<'a>(query: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<i64> {
+    candidates.into_iter()
+        .filter(|candidate| !candidate.is_empty())
+        .filter_map(|candidate| score(query, candidate))
+        .max()
+}
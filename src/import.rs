@@ -0,0 +1,166 @@
+use std::path::Path;
+
+use color_eyre::{eyre::eyre, Result};
+use rusqlite::Connection;
+
+use crate::{db, export, signal, types};
+
+#[derive(Default)]
+pub struct Summary {
+    pub imported: usize,
+    // already stored, reactions or nothing to show
+    pub skipped: usize,
+    // lines that are not json or not a message, signal-cli logs have other output mixed in
+    pub unreadable: usize,
+}
+
+// our own json export or signal-cli's `receive --output=json` / jsonRpc output, one envelope per line.
+// account is used for lines without one and leaves out the lines of other accounts
+pub fn import_file(db: &mut Connection, path: &Path, account: Option<&str>) -> Result<Summary> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| eyre!("Could not read {}: {}", path.display(), err))?;
+
+    let transaction = db.transaction()?;
+
+    let summary = match serde_json::from_str::<types::ArchivedConversation>(&content) {
+        Ok(archive) => import_archive(&transaction, archive, path, account)?,
+        Err(_) => import_log(&transaction, &content, account)?,
+    };
+
+    transaction.commit()?;
+
+    Ok(summary)
+}
+
+fn import_archive(
+    db: &Connection,
+    archive: types::ArchivedConversation,
+    path: &Path,
+    account: Option<&str>,
+) -> Result<Summary> {
+    if archive.version > export::VERSION {
+        return Err(eyre!("{} was exported by a newer signal-tui (format {}), update to import it", path.display(), archive.version));
+    }
+
+    let account_number = account.unwrap_or(&archive.account);
+    let conversation_type = if archive.conversation.kind == "group" { 0 } else { 1 };
+    let conversation_id = archive.conversation.id;
+
+    // attachments were copied next to the export and are linked relative to it
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let mut summary = Summary::default();
+    let mut newest = 0;
+
+    for message in archive.messages {
+        for reaction in &message.reactions {
+            let reaction_on = types::SignalReaction {
+                emoji: reaction.emoji.clone(),
+                target_author_uuid: Some(message.source_uuid.clone()),
+                target_sent_timestamp: message.timestamp,
+                is_remove: false,
+            };
+            db::save_reaction(db, account_number, &reaction_on, &reaction.source_uuid, &reaction.source_name)?;
+        }
+
+        if db::has_message(db, account_number, conversation_type, &conversation_id, message.timestamp, &message.message)? {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let ours = message.source_uuid == "self" || message.source_number.as_deref() == Some(account_number);
+        let (destination_uuid, group_id) = match (conversation_type, ours) {
+            (0, _) => (None, Some(conversation_id.clone())),
+            (_, true) => (Some(conversation_id.clone()), None),
+            (_, false) => (Some("self".to_string()), None),
+        };
+
+        let attachments: Vec<types::SignalAttachment> = message.attachments.into_iter()
+            .map(|mut attachment| {
+                attachment.path = attachment.path
+                    .map(|path| base.join(path))
+                    .filter(|path| path.is_file())
+                    .map(|path| std::fs::canonicalize(&path).unwrap_or(path).to_string_lossy().to_string());
+                attachment
+            })
+            .collect();
+        let attachments = (!attachments.is_empty()).then(|| serde_json::to_string(&attachments)).transpose()?;
+
+        db.execute(
+            "INSERT INTO messages (id, sourceUuid, sourceNumber, sourceName, destinationUuid, groupId, message, timestamp, expiresInSeconds, pending, accountNumber, quoteTimestamp, quoteAuthor, quoteText, attachments) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+            rusqlite::params![
+                signal::generate_id(),
+                message.source_uuid,
+                message.source_number,
+                message.source_name,
+                destination_uuid,
+                group_id,
+                message.message,
+                message.timestamp,
+                message.expires_in_seconds,
+                0,
+                account_number,
+                message.quote.as_ref().map(|quote| quote.timestamp),
+                message.quote.as_ref().and_then(|quote| quote.author.clone()),
+                message.quote.as_ref().and_then(|quote| quote.text.clone()),
+                attachments
+            ],
+        )?;
+
+        newest = newest.max(message.timestamp);
+        summary.imported += 1;
+    }
+
+    // history is old news, it shouldnt show up as unread
+    if newest > 0 {
+        db::mark_read(db, account_number, &conversation_id, newest)?;
+    }
+
+    Ok(summary)
+}
+
+fn import_log(db: &Connection, content: &str, account: Option<&str>) -> Result<Summary> {
+    let mut summary = Summary::default();
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let Ok(value) = serde_json::from_str::<serde_json::Value>(line) else {
+            summary.unreadable += 1;
+            continue;
+        };
+
+        // jsonRpc wraps the envelope in params, or in params.result for subscriptions
+        let result = if value["method"] == "receive" {
+            let params = &value["params"];
+            if params["result"].is_object() { &params["result"] } else { params }
+        } else {
+            &value
+        };
+
+        let Ok(envelope) = serde_json::from_value::<types::SignalMessageEventEnvelope>(result["envelope"].clone()) else {
+            summary.unreadable += 1;
+            continue;
+        };
+
+        let account_number = match (result["account"].as_str(), account) {
+            (Some(line_account), Some(account)) if line_account != account => continue,
+            (Some(line_account), _) => line_account,
+            (None, Some(account)) => account,
+            (None, None) => return Err(eyre!("line {} does not say which account it is for, pass --account", number + 1)),
+        };
+
+        match signal::store_envelope(db, account_number, envelope)? {
+            Some(stored) => {
+                db::mark_read(db, account_number, &stored.conversation_id, stored.timestamp)?;
+                summary.imported += 1;
+            }
+            None => summary.skipped += 1,
+        }
+    }
+
+    Ok(summary)
+}
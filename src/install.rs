@@ -0,0 +1,614 @@
+use std::{fs::File, io::{Read, Write}, path::{Path, PathBuf}, time::Duration};
+
+use color_eyre::{eyre::eyre, Result};
+use crossterm::event::{self, Event, KeyCode};
+use flate2::read::GzDecoder;
+use ratatui::{layout::{Alignment, Constraint, Direction, Layout}, style::{Color, Style}, widgets::{Block, BorderType, Borders, Gauge, Paragraph, Wrap}, DefaultTerminal};
+use reqwest::{blocking::Client, header, StatusCode};
+use sha2::{Digest, Sha256};
+use tar::Archive;
+
+use crate::signal::generate_id;
+
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+
+// signal-cli versions the json-rpc handling has been tested against, the max is exclusive
+const MIN_VERSION: (u32, u32, u32) = (0, 13, 0);
+const MAX_VERSION: (u32, u32, u32) = (0, 14, 0);
+
+// sha-256 of the release tarballs this build has been tested with by file name, both builds of
+// config::DEFAULT_SIGNAL_CLI_VERSION have to be here, the default install refuses to go on without them.
+// signal_cli.sha256 in the config comes first, other versions fall back to the digest github publishes after asking
+const MANIFEST: &[(&str, &str)] = &[];
+
+// graalvm builds that dont need java, only published for linux x86_64
+pub fn native_available() -> bool {
+    std::env::consts::OS == "linux" && std::env::consts::ARCH == "x86_64"
+}
+
+fn asset_name(version: &str, native: bool) -> String {
+    if native {
+        format!("signal-cli-{}-Linux-native.tar.gz", version)
+    } else {
+        format!("signal-cli-{}.tar.gz", version)
+    }
+}
+
+// release tarballs are named after their version, None for one that was renamed
+fn tarball_version(tarball: &Path) -> Option<&str> {
+    let name = tarball.file_name()?.to_str()?;
+    let version = name.strip_prefix("signal-cli-")?.strip_suffix(".tar.gz")?;
+    Some(version.strip_suffix("-Linux-native").unwrap_or(version))
+}
+
+// the java build ships its jars in lib, the native one is a single binary
+pub fn is_native_install(dir: &Path) -> bool {
+    dir.join("bin").join(launcher_name()).is_file() && !dir.join("lib").exists()
+}
+
+fn launcher_name() -> &'static str {
+    match std::env::consts::OS {
+        "windows" => "signal-cli.bat",
+        _ => "signal-cli",
+    }
+}
+
+// like `which`, for signal_cli.use_path
+pub fn find_in_path(name: &str) -> Option<PathBuf> {
+    let extensions: &[&str] = match std::env::consts::OS {
+        "windows" => &["", ".bat", ".cmd", ".exe"],
+        _ => &[""],
+    };
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .flat_map(|dir| extensions.iter().map(move |ext| dir.join(format!("{}{}", name, ext))))
+        .find(|path| path.is_file())
+}
+
+// makes sure there is a signal-cli to start, installing the bundled one (java or native build) if needed
+pub fn ensure_installed(terminal: &mut DefaultTerminal, native: bool) -> Result<()> {
+    let config = crate::config::get();
+    let settings = &config.signal_cli;
+    let path = config.signal_cli_path();
+
+    if settings.path.is_some() || settings.use_path {
+        // a bare name is looked up in $PATH when it gets started
+        let found = path.is_file() || (path.components().count() == 1 && find_in_path(&path.to_string_lossy()).is_some());
+
+        if !found {
+            return Err(eyre!(
+                "signal-cli not found at {}\nInstall it or remove signal_cli.path / signal_cli.use_path from the config to use the bundled one",
+                path.display()
+            ));
+        }
+
+        return Ok(());
+    }
+
+    let data_dir = config.data_dir();
+    let target = data_dir.join("signal-cli");
+
+    if path.is_file() && is_native_install(&target) == native {
+        return Ok(());
+    }
+
+    install(terminal, &data_dir, &settings.version, &target, native)
+}
+
+pub fn client() -> Result<Client> {
+    Ok(Client::builder()
+        .user_agent("signal-tui")
+        .connect_timeout(Duration::from_secs(30))
+        .timeout(Duration::from_secs(30 * 60))
+        .build()?)
+}
+
+// downloads (or takes signal_cli.tarball), verifies and installs into target
+pub fn install(terminal: &mut DefaultTerminal, data_dir: &Path, version: &str, target: &Path, native: bool) -> Result<()> {
+    let settings = &crate::config::get().signal_cli;
+    let client = client()?;
+    let asset = asset_name(version, native);
+
+    // a tarball left in the config after bumping signal_cli.version would be installed and rolled back on every upgrade
+    let stale = settings.tarball.as_deref()
+        .and_then(tarball_version)
+        .filter(|tarball| parse_version(tarball) != parse_version(version));
+    let configured = settings.tarball.as_ref().filter(|_| stale.is_none());
+
+    let (tarball, downloaded) = match configured {
+        Some(tarball) => (tarball.clone(), false),
+        None => {
+            let url = format!("https://github.com/AsamK/signal-cli/releases/download/v{}/{}", version, asset);
+            let title = format!("Downloading signal-cli {}...", version);
+
+            let tarball = download(terminal, &client, &url, &data_dir.join(&asset), &title)
+                .map_err(|err| match &stale {
+                    Some(stale) => err.wrap_err(format!("signal_cli.tarball is signal-cli {}, download {} yourself and set it there", stale, asset)),
+                    None => err.wrap_err("Download it yourself and set signal_cli.tarball in the config to install it"),
+                })?;
+
+            (tarball, true)
+        }
+    };
+
+    if settings.verify {
+        draw_progress(terminal, &format!("Verifying signal-cli {}...", version), None)?;
+
+        let result = expected_sha256(&client, version, &asset, stale.is_none())
+            .and_then(|(expected, pinned)| verify(&tarball, &expected).map(|()| pinned))
+            .and_then(|pinned| {
+                if pinned || confirm_unpinned(terminal, version)? {
+                    Ok(())
+                } else {
+                    Err(eyre!("Installing signal-cli {} was cancelled", version))
+                }
+            });
+
+        if let Err(err) = result {
+            // a bad download shouldnt be resumed next time
+            if downloaded {
+                let _ = std::fs::remove_file(&tarball);
+            }
+            return Err(err);
+        }
+    }
+
+    draw_progress(terminal, &format!("Installing signal-cli {}...", version), None)?;
+
+    install_tarball(&tarball, data_dir, target)?;
+
+    if downloaded {
+        let _ = std::fs::remove_file(&tarball);
+    }
+
+    Ok(())
+}
+
+// extracts next to the target and renames it into place, so a failure never leaves a half installed signal-cli
+pub fn install_tarball(tarball: &Path, data_dir: &Path, target: &Path) -> Result<()> {
+    install_archive(tarball, data_dir, target, |extract, staging| {
+        // the java build has a single signal-cli-<version> directory, the native one just the binary
+        let native_binary = extract.join("signal-cli");
+        if native_binary.is_file() {
+            let dir = staging.join("native");
+            std::fs::create_dir_all(dir.join("bin"))?;
+            std::fs::rename(&native_binary, dir.join("bin").join("signal-cli"))?;
+            return Ok(dir);
+        }
+
+        find_extracted(extract, |path| path.join("bin").join(launcher_name()).is_file())
+            .ok_or_else(|| eyre!("{} does not look like a signal-cli release", tarball.display()))
+    })
+}
+
+// first top level directory of an extracted archive that matches
+pub fn find_extracted(extract: &Path, matches: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    std::fs::read_dir(extract).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| matches(path))
+}
+
+// extracts a .tar.gz into a staging dir in data_dir, root picks what to install out of it (given the extract and staging dirs),
+// and that gets renamed to target. rename is atomic on the same filesystem, so target is either the old or the new version
+pub fn install_archive(
+    tarball: &Path,
+    data_dir: &Path,
+    target: &Path,
+    root: impl FnOnce(&Path, &Path) -> Result<PathBuf>,
+) -> Result<()> {
+    let staging = data_dir.join(format!(".install-{}", generate_id()));
+
+    let result = (|| {
+        std::fs::create_dir_all(&staging)?;
+
+        let extract = staging.join("extract");
+        Archive::new(GzDecoder::new(File::open(tarball)?))
+            .unpack(&extract)
+            .map_err(|err| eyre!("Could not extract {}: {}", tarball.display(), err))?;
+
+        let extracted = root(&extract, &staging)?;
+
+        // rename wont replace a directory, so an old install gets moved aside first and put back if that fails
+        let previous = staging.join("previous");
+        if target.exists() {
+            std::fs::rename(target, &previous)?;
+        }
+
+        if let Err(err) = std::fs::rename(&extracted, target) {
+            if previous.exists() {
+                let _ = std::fs::rename(&previous, target);
+            }
+            return Err(err.into());
+        }
+
+        Ok(())
+    })();
+
+    let _ = std::fs::remove_dir_all(&staging);
+
+    result
+}
+
+// retries a few times, picking up where the last attempt (or the last run) stopped. the file is only at dest once it is complete
+pub fn download(terminal: &mut DefaultTerminal, client: &Client, url: &str, dest: &Path, title: &str) -> Result<PathBuf> {
+    let part = dest.with_file_name(format!("{}.part", dest.file_name().unwrap_or_default().to_string_lossy()));
+
+    let mut attempt = 1;
+    loop {
+        match download_attempt(terminal, client, url, &part, title) {
+            Ok(()) => break,
+            Err(err) if attempt < DOWNLOAD_ATTEMPTS => {
+                crate::debug_to_file(format!("Download of {} failed: {}", url, err));
+                draw_progress(terminal, &format!("Download failed, retrying ({}/{}): {}", attempt, DOWNLOAD_ATTEMPTS - 1, err), None)?;
+                std::thread::sleep(Duration::from_secs(2 * attempt as u64));
+                attempt += 1;
+            }
+            Err(err) => return Err(err.wrap_err(format!("Could not download {}", url))),
+        }
+    }
+
+    std::fs::rename(&part, dest)?;
+
+    Ok(dest.to_path_buf())
+}
+
+fn download_attempt(terminal: &mut DefaultTerminal, client: &Client, url: &str, part: &Path, title: &str) -> Result<()> {
+    let existing = std::fs::metadata(part).map(|metadata| metadata.len()).unwrap_or(0);
+
+    let mut request = client.get(url);
+    if existing > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", existing));
+    }
+
+    let response = request.send()?;
+
+    // the part file is already complete, verification will tell if it isnt what we wanted
+    if existing > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Ok(());
+    }
+
+    let mut response = response.error_for_status()?;
+
+    // servers that ignore the range header send everything again
+    let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+    let mut downloaded = if resumed { existing } else { 0 };
+    let total = response.content_length().map(|length| length + downloaded);
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(part)?;
+
+    let mut buffer = [0; 8192];
+    let mut last_percent = None;
+
+    loop {
+        let b = response.read(&mut buffer)?;
+        if b == 0 {
+            break;
+        }
+
+        file.write_all(&buffer[..b])?;
+        downloaded += b as u64;
+
+        let percent = total.map(|total| (downloaded as f64 / total as f64 * 100.0).min(100.0) as u16);
+        if percent != last_percent {
+            draw_progress(terminal, title, percent)?;
+            last_percent = percent;
+        }
+    }
+
+    file.flush()?;
+
+    if let Some(total) = total
+        && downloaded < total {
+        return Err(eyre!("Connection closed after {} of {} bytes", downloaded, total));
+    }
+
+    Ok(())
+}
+
+// and whether it is pinned, github's comes from the same place as the download.
+// signal_cli.sha256 goes with signal_cli.tarball, so it is left out when that is for another version
+fn expected_sha256(client: &Client, version: &str, asset: &str, use_configured: bool) -> Result<(String, bool)> {
+    let settings = &crate::config::get().signal_cli;
+
+    if let Some(sha256) = settings.sha256.as_ref().filter(|_| use_configured) {
+        return Ok((sha256.to_lowercase(), true));
+    }
+
+    if let Some((_, sha256)) = MANIFEST.iter().find(|(name, _)| *name == asset) {
+        return Ok((sha256.to_string(), true));
+    }
+
+    // github's digest is only good enough for a version someone picked themselves
+    if version == crate::config::DEFAULT_SIGNAL_CLI_VERSION {
+        return Err(eyre!(
+            "No pinned checksum for {} in this build of signal-tui\nSet signal_cli.sha256 in the config to one you trust, or signal_cli.verify = false to skip the check",
+            asset
+        ));
+    }
+
+    github_digest(client, version, asset).map(|digest| (digest, false)).map_err(|err| eyre!(
+        "No checksum known for signal-cli {} ({})\nSet signal_cli.sha256 in the config, or signal_cli.verify = false to skip the check",
+        version, err
+    ))
+}
+
+// github lists a sha256 digest for every release asset
+fn github_digest(client: &Client, version: &str, asset: &str) -> Result<String> {
+    let url = format!("https://api.github.com/repos/AsamK/signal-cli/releases/tags/v{}", version);
+    let release: serde_json::Value = serde_json::from_str(&client.get(&url).send()?.error_for_status()?.text()?)?;
+
+    release["assets"].as_array()
+        .and_then(|assets| assets.iter().find(|a| a["name"] == asset))
+        .and_then(|a| a["digest"].as_str())
+        .and_then(|digest| digest.strip_prefix("sha256:"))
+        .map(|digest| digest.to_lowercase())
+        .ok_or_else(|| eyre!("the release has no digest for {}", asset))
+}
+
+// github's digest only catches broken downloads, not a release that was tampered with
+fn confirm_unpinned(terminal: &mut DefaultTerminal, version: &str) -> Result<bool> {
+    let message = format!(
+        "There is no pinned checksum for signal-cli {} in this signal-tui.\nThe download matches the one GitHub lists for it, but that comes from the same place,\nso it only shows the download is not broken.\nSet signal_cli.sha256 in the config to check it against one you trust.\n\n'c' - install anyway | 'q' - cancel",
+        version
+    );
+
+    loop {
+        draw_message(terminal, "Unpinned checksum", &message)?;
+
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('c') | KeyCode::Enter => return Ok(true),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 65536];
+
+    loop {
+        let b = file.read(&mut buffer)?;
+        if b == 0 {
+            break;
+        }
+        hasher.update(&buffer[..b]);
+    }
+
+    Ok(hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub fn verify(tarball: &Path, expected: &str) -> Result<()> {
+    let actual = sha256_file(tarball)?;
+
+    if actual != expected.to_lowercase() {
+        return Err(eyre!(
+            "Checksum mismatch for {}\nexpected {}\ngot      {}",
+            tarball.display(), expected, actual
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn draw_progress(terminal: &mut DefaultTerminal, title: &str, percent: Option<u16>) -> Result<()> {
+    terminal.draw(|f| {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .margin(5)
+            .constraints(
+                [
+                    Constraint::Length(2),
+                    Constraint::Length(3),
+                    Constraint::Min(0),
+                ]
+                .as_ref(),
+            )
+            .split(f.area());
+
+        let title = Paragraph::new(title)
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true });
+
+        f.render_widget(title, chunks[0]);
+
+        if let Some(percent) = percent {
+            let gauge = Gauge::default()
+                .block(Block::default().borders(Borders::ALL))
+                .gauge_style(Style::default().fg(Color::Green))
+                .label(format!("{}%", percent))
+                .percent(percent);
+
+            f.render_widget(gauge, chunks[1]);
+        }
+    })?;
+
+    Ok(())
+}
+
+// "0.13.14" or "0.13.14-SNAPSHOT"
+pub fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = version.trim().split(['.', '-', '+']);
+
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = parts.next().and_then(|patch| patch.parse().ok()).unwrap_or(0);
+
+    Some((major, minor, patch))
+}
+
+fn format_version(version: (u32, u32, u32)) -> String {
+    format!("{}.{}.{}", version.0, version.1, version.2)
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VersionChoice {
+    Continue,
+    Upgrade,
+    Quit,
+}
+
+// compares the running signal-cli with what the app supports and the configured version, and asks what to do if they differ
+pub fn check_version(terminal: &mut DefaultTerminal, running: Option<&str>) -> Result<VersionChoice> {
+    let config = crate::config::get();
+    // a daemon is run by the user, we cant swap it out
+    let bundled = config.signal_cli.path.is_none() && !config.signal_cli.use_path && !config.transport.is_daemon();
+
+    // it didnt answer at all, list_accounts reports that
+    let Some(running) = running else {
+        return Ok(VersionChoice::Continue);
+    };
+
+    let parsed = parse_version(running);
+    let wanted = parse_version(&config.signal_cli.version);
+
+    let supported = parsed.is_some_and(|version| version >= MIN_VERSION && version < MAX_VERSION);
+    let can_upgrade = bundled && wanted.is_some() && parsed < wanted;
+
+    if supported && !can_upgrade {
+        return Ok(VersionChoice::Continue);
+    }
+
+    let mut message = if supported {
+        format!("signal-cli {} is installed, {} is available.", running, config.signal_cli.version)
+    } else {
+        format!(
+            "signal-cli {} is not supported, signal-tui works with {} up to (not including) {}.\nSome things may not work.",
+            running, format_version(MIN_VERSION), format_version(MAX_VERSION)
+        )
+    };
+
+    message.push_str("\n\n");
+    if can_upgrade {
+        message.push_str(&format!("'u' - upgrade to {} | ", config.signal_cli.version));
+    } else if !bundled {
+        message.push_str("Update the signal-cli set in the config to upgrade.\n\n");
+    }
+    message.push_str("'c' - continue | 'q' - quit");
+
+    loop {
+        draw_message(terminal, "signal-cli version", &message)?;
+
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('u') if can_upgrade => return Ok(VersionChoice::Upgrade),
+                KeyCode::Char('c') | KeyCode::Enter => return Ok(VersionChoice::Continue),
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(VersionChoice::Quit),
+                _ => {}
+            }
+        }
+    }
+}
+
+// installs the new version next to the current one, switches over and goes back if the new one wont start
+pub fn upgrade(terminal: &mut DefaultTerminal, old_version: &str, new_version: &str) -> Result<()> {
+    let data_dir = crate::config::get().data_dir();
+    let current = data_dir.join("signal-cli");
+    let next = data_dir.join(format!("signal-cli-{}", new_version));
+    let previous = data_dir.join(format!("signal-cli-{}.previous", old_version));
+
+    // leftovers from an upgrade that got interrupted
+    let _ = std::fs::remove_dir_all(&next);
+    let _ = std::fs::remove_dir_all(&previous);
+
+    install(terminal, &data_dir, new_version, &next, is_native_install(&current))?;
+
+    std::fs::rename(&current, &previous)?;
+    if let Err(err) = std::fs::rename(&next, &current) {
+        std::fs::rename(&previous, &current)?;
+        return Err(err.into());
+    }
+
+    draw_progress(terminal, &format!("Starting signal-cli {}...", new_version), None)?;
+
+    match installed_version(&current.join("bin").join(launcher_name())) {
+        Ok(version) if parse_version(&version) == parse_version(new_version) => {
+            let _ = std::fs::remove_dir_all(&previous);
+            Ok(())
+        }
+        result => {
+            let reason = match result {
+                Ok(version) => format!("it reports version {}", version),
+                Err(err) => err.to_string(),
+            };
+
+            std::fs::rename(&current, &next)?;
+            std::fs::rename(&previous, &current)?;
+            let _ = std::fs::remove_dir_all(&next);
+
+            Err(eyre!("signal-cli {} failed to start ({}), went back to {}", new_version, reason, old_version))
+        }
+    }
+}
+
+// runs `signal-cli --version`, which also checks that it starts at all
+fn installed_version(launcher: &Path) -> Result<String> {
+    let mut command = std::process::Command::new(launcher);
+    if let Some(java_home) = crate::java::home() {
+        command.env("JAVA_HOME", java_home);
+    }
+
+    let output = command
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("{}", stderr.lines().last().unwrap_or("exited with an error").trim()));
+    }
+
+    // "signal-cli 0.13.14"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout.split_whitespace().last()
+        .map(|version| version.to_string())
+        .ok_or_else(|| eyre!("no version in the output"))
+}
+
+// a message with a border, for setup screens
+pub fn draw_message(terminal: &mut DefaultTerminal, title: &str, message: &str) -> Result<()> {
+    terminal.draw(|f| {
+        let width = (message.lines().map(|line| line.chars().count()).max().unwrap_or(0) as u16 + 4).min(80);
+        let height = message.lines().count() as u16 + 4;
+        let area = crate::ui::centered_rect(f.area(), width, height);
+
+        let block = Block::default()
+            .title(format!(" {} ", title))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(crate::theme::get().borders.style());
+
+        f.render_widget(
+            Paragraph::new(message)
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: false })
+                .block(block),
+            area
+        );
+    })?;
+
+    Ok(())
+}
+
+// shows an error until a key is pressed
+pub fn show_error(terminal: &mut DefaultTerminal, title: &str, message: &str) -> Result<()> {
+    let message = format!("{}\n\nPress any key to continue", message);
+
+    loop {
+        draw_message(terminal, title, &message)?;
+
+        if let Event::Key(_) = event::read()? {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::{path::{Path, PathBuf}, process::{Command, Stdio}, sync::OnceLock};
+
+use color_eyre::{eyre::eyre, Result};
+use crossterm::event::{self, Event, KeyCode};
+use ratatui::DefaultTerminal;
+
+use crate::install;
+
+// the java signal-cli gets started with, picked by preflight
+static JAVA_HOME: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn home() -> Option<&'static PathBuf> {
+    JAVA_HOME.get()
+}
+
+// the java release each signal-cli version needs
+pub fn required_version(signal_cli_version: &str) -> u32 {
+    match install::parse_version(signal_cli_version) {
+        Some(version) if version < (0, 13, 0) => 17,
+        _ => 21,
+    }
+}
+
+pub struct Java {
+    pub home: PathBuf,
+    // None if it didnt run or printed something we dont understand
+    pub version: Option<u32>,
+}
+
+fn jre_dir() -> PathBuf {
+    crate::config::get().data_dir().join("jre")
+}
+
+// the macos jre keeps its java home in Contents/Home
+fn java_home_in(dir: &Path) -> PathBuf {
+    let mac = dir.join("Contents").join("Home");
+    if mac.is_dir() { mac } else { dir.to_path_buf() }
+}
+
+fn java_binary(home: &Path) -> PathBuf {
+    home.join("bin").join(match std::env::consts::OS {
+        "windows" => "java.exe",
+        _ => "java",
+    })
+}
+
+// JAVA_HOME, then the runtime downloaded into the data dir, then java on $PATH
+pub fn find() -> Vec<Java> {
+    let mut homes = vec![];
+
+    if let Some(home) = std::env::var_os("JAVA_HOME") {
+        homes.push(PathBuf::from(home));
+    }
+
+    homes.push(java_home_in(&jre_dir()));
+
+    if let Some(java) = install::find_in_path("java") {
+        // /usr/bin/java is usually a symlink into the real java home
+        let java = std::fs::canonicalize(&java).unwrap_or(java);
+        if let Some(home) = java.parent().and_then(|bin| bin.parent()) {
+            homes.push(home.to_path_buf());
+        }
+    }
+
+    let mut found: Vec<Java> = vec![];
+    for home in homes {
+        if !java_binary(&home).is_file() || found.iter().any(|java| java.home == home) {
+            continue;
+        }
+
+        let version = java_version(&home);
+        found.push(Java { home, version });
+    }
+
+    found
+}
+
+fn java_version(home: &Path) -> Option<u32> {
+    let output = Command::new(java_binary(home))
+        .arg("-version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+
+    parse_java_version(&String::from_utf8_lossy(&output.stderr))
+}
+
+// `java -version` prints eg. openjdk version "21.0.2" 2024-01-16, or java version "1.8.0_351" for old ones
+pub fn parse_java_version(output: &str) -> Option<u32> {
+    let quoted = output.split('"').nth(1)?;
+    let mut parts = quoted.split(['.', '_', '-', '+']);
+
+    let first: u32 = parts.next()?.parse().ok()?;
+    if first == 1 {
+        parts.next()?.parse().ok()
+    } else {
+        Some(first)
+    }
+}
+
+// for the command line, which has no setup screen: the first java that is new enough, if there is one
+pub fn select() {
+    let required = required_version(&crate::config::get().signal_cli.version);
+
+    if let Some(java) = find().into_iter().find(|java| java.version.is_some_and(|version| version >= required)) {
+        let _ = JAVA_HOME.set(java.home);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Preflight {
+    // start the java build of signal-cli
+    Ready,
+    // use the native build, no java needed
+    Native,
+    Quit,
+}
+
+// makes sure the bundled signal-cli has a java to run on before it gets installed or started
+pub fn preflight(terminal: &mut DefaultTerminal) -> Result<Preflight> {
+    let config = crate::config::get();
+    let settings = &config.signal_cli;
+
+    // a signal-cli from the config or $PATH is set up by the user
+    if settings.path.is_some() || settings.use_path {
+        return Ok(Preflight::Ready);
+    }
+
+    if settings.native {
+        if !install::native_available() {
+            return Err(eyre!("signal_cli.native is set, but the native signal-cli build only exists for linux x86_64"));
+        }
+        return Ok(Preflight::Native);
+    }
+
+    if install::is_native_install(&config.data_dir().join("signal-cli")) {
+        return Ok(Preflight::Native);
+    }
+
+    let required = required_version(&settings.version);
+
+    loop {
+        let found = find();
+
+        if let Some(java) = found.iter().find(|java| java.version.is_some_and(|version| version >= required)) {
+            let _ = JAVA_HOME.set(java.home.clone());
+            return Ok(Preflight::Ready);
+        }
+
+        let can_download = adoptium_platform().is_some();
+        let message = setup_message(&settings.version, required, &found, can_download);
+
+        install::draw_message(terminal, "Java needed", &message)?;
+
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+
+        match key.code {
+            KeyCode::Char('d') if can_download => {
+                if let Err(err) = download_jre(terminal, required) {
+                    install::show_error(terminal, "Java download failed", &format!("{:#}", err))?;
+                }
+            }
+            KeyCode::Char('n') if install::native_available() => return Ok(Preflight::Native),
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(Preflight::Quit),
+            _ => {}
+        }
+    }
+}
+
+fn setup_message(signal_cli_version: &str, required: u32, found: &[Java], can_download: bool) -> String {
+    let mut message = format!("signal-cli {} needs Java {} or newer.\n\n", signal_cli_version, required);
+
+    if found.is_empty() {
+        message.push_str("No Java found in JAVA_HOME or PATH.\n");
+    } else {
+        for java in found {
+            message.push_str(&match java.version {
+                Some(version) => format!("Java {} in {} is too old.\n", version, java.home.display()),
+                None => format!("The Java in {} does not run.\n", java.home.display()),
+            });
+        }
+    }
+
+    message.push_str(&format!(
+        "\nInstall Java {} from your package manager or adoptium.net,\nthen set JAVA_HOME or add it to PATH and press 'r'.\n\n",
+        required
+    ));
+
+    if can_download {
+        message.push_str(&format!("'d' - download Java {} into {}\n", required, jre_dir().display()));
+    }
+    if install::native_available() {
+        message.push_str("'n' - use the native signal-cli build, no Java needed\n");
+    }
+    message.push_str("'r' - check again | 'q' - quit");
+
+    message
+}
+
+// adoptium names for this platform, windows only gets zips which we cant extract
+fn adoptium_platform() -> Option<(&'static str, &'static str)> {
+    let os = match std::env::consts::OS {
+        "linux" => "linux",
+        "macos" => "mac",
+        _ => return None,
+    };
+
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => "x64",
+        "aarch64" => "aarch64",
+        _ => return None,
+    };
+
+    Some((os, arch))
+}
+
+// latest eclipse temurin jre for the feature release, checked against the checksum adoptium lists for it
+fn download_jre(terminal: &mut DefaultTerminal, feature: u32) -> Result<()> {
+    let (os, arch) = adoptium_platform().ok_or_else(|| eyre!("No Java download for this platform"))?;
+    let client = install::client()?;
+
+    install::draw_progress(terminal, &format!("Looking up Java {}...", feature), None)?;
+
+    let url = format!(
+        "https://api.adoptium.net/v3/assets/latest/{}/hotspot?architecture={}&image_type=jre&os={}&vendor=eclipse",
+        feature, arch, os
+    );
+    let assets: serde_json::Value = serde_json::from_str(&client.get(&url).send()?.error_for_status()?.text()?)?;
+
+    let package = &assets[0]["binary"]["package"];
+    let (Some(link), Some(checksum), Some(name)) = (
+        package["link"].as_str(),
+        package["checksum"].as_str(),
+        package["name"].as_str(),
+    ) else {
+        return Err(eyre!("No Java {} runtime listed for {} {}", feature, os, arch));
+    };
+
+    let data_dir = crate::config::get().data_dir();
+    let tarball = install::download(terminal, &client, link, &data_dir.join(name), &format!("Downloading Java {}...", feature))?;
+
+    install::draw_progress(terminal, &format!("Verifying Java {}...", feature), None)?;
+
+    let result = install::verify(&tarball, checksum).and_then(|()| {
+        install::draw_progress(terminal, &format!("Installing Java {}...", feature), None)?;
+
+        install::install_archive(&tarball, &data_dir, &jre_dir(), |extract, _| {
+            install::find_extracted(extract, |path| java_binary(&java_home_in(path)).is_file())
+                .ok_or_else(|| eyre!("{} does not contain a Java runtime", name))
+        })
+    });
+
+    let _ = std::fs::remove_file(&tarball);
+
+    result
+}
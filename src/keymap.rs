@@ -0,0 +1,575 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+static KEYMAP: OnceLock<Keymap> = OnceLock::new();
+
+// where the key press happens, each has its own bindings
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Context {
+    Sidebar,
+    Conversation,
+    Input,
+}
+
+impl Context {
+    pub const ALL: [Context; 3] = [Context::Sidebar, Context::Conversation, Context::Input];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Context::Sidebar => "sidebar",
+            Context::Conversation => "conversation",
+            Context::Input => "input",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Back,
+    Quit,
+    Up,
+    Down,
+    Top,
+    Bottom,
+    PageUp,
+    PageDown,
+    Select,
+    FocusInput,
+    Send,
+    Newline,
+    ExternalEditor,
+    CursorLeft,
+    CursorRight,
+    WordLeft,
+    WordRight,
+    LineStart,
+    LineEnd,
+    HistoryPrevious,
+    HistoryNext,
+    DeleteBackward,
+    DeleteForward,
+    DeleteWord,
+    DeleteLine,
+    Complete,
+    ToggleSort,
+    SidebarWider,
+    SidebarNarrower,
+    CycleMute,
+    Search,
+    SearchNext,
+    SearchPrevious,
+    CommandMode,
+    Switcher,
+    SwitchAccount,
+    ToggleUnified,
+    Devices,
+    Export,
+    Help,
+    // used in the config to unbind a default key
+    Unbound,
+}
+
+// name used in the config and command mode, description for the help overlay
+const ACTIONS: &[(Action, &str, &str)] = &[
+    (Action::Back, "back", "unfocus input / close conversation / exit"),
+    (Action::Quit, "quit", "exit signal-tui"),
+    (Action::Up, "up", "move up"),
+    (Action::Down, "down", "move down"),
+    (Action::Top, "top", "jump to the top"),
+    (Action::Bottom, "bottom", "jump to the bottom"),
+    (Action::PageUp, "page_up", "move up a page"),
+    (Action::PageDown, "page_down", "move down a page"),
+    (Action::Select, "select", "open conversation / toggle section"),
+    (Action::FocusInput, "focus_input", "start typing a message"),
+    (Action::Send, "send", "send the message"),
+    (Action::Newline, "newline", "insert a line break"),
+    (Action::ExternalEditor, "external_editor", "write the message in $EDITOR"),
+    (Action::CursorLeft, "cursor_left", "move the cursor left"),
+    (Action::CursorRight, "cursor_right", "move the cursor right"),
+    (Action::WordLeft, "word_left", "move the cursor to the previous word"),
+    (Action::WordRight, "word_right", "move the cursor to the next word"),
+    (Action::LineStart, "line_start", "move the cursor to the start of the line"),
+    (Action::LineEnd, "line_end", "move the cursor to the end of the line"),
+    (Action::HistoryPrevious, "history_previous", "line up / previous sent message"),
+    (Action::HistoryNext, "history_next", "line down / next sent message"),
+    (Action::DeleteBackward, "delete_backward", "delete the character before the cursor"),
+    (Action::DeleteForward, "delete_forward", "delete the character after the cursor"),
+    (Action::DeleteWord, "delete_word", "delete the word before the cursor"),
+    (Action::DeleteLine, "delete_line", "delete to the start of the line"),
+    (Action::Complete, "complete", "complete a /command or file path"),
+    (Action::ToggleSort, "toggle_sort", "sort by recent activity / default order"),
+    (Action::SidebarWider, "sidebar_wider", "make the sidebar wider"),
+    (Action::SidebarNarrower, "sidebar_narrower", "make the sidebar narrower"),
+    (Action::CycleMute, "cycle_mute", "notify for all / mentions / nothing"),
+    (Action::Search, "search", "search names or messages"),
+    (Action::SearchNext, "search_next", "next search match"),
+    (Action::SearchPrevious, "search_previous", "previous search match"),
+    (Action::CommandMode, "command_mode", "run a command by name"),
+    (Action::Switcher, "switcher", "jump to a conversation by name"),
+    (Action::SwitchAccount, "switch_account", "switch to the next linked account"),
+    (Action::ToggleUnified, "toggle_unified", "show the conversations of all accounts / one account"),
+    (Action::Devices, "devices", "manage the linked devices of the account"),
+    (Action::Export, "export", "export the open conversation, or every one of the account"),
+    (Action::Help, "help", "show key bindings"),
+    (Action::Unbound, "none", "does nothing"),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|(_, n, _)| *n == name).map(|(action, _, _)| *action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTIONS.iter().find(|(a, _, _)| *a == self).map(|(_, name, _)| *name).unwrap_or("")
+    }
+
+    pub fn description(self) -> &'static str {
+        ACTIONS.iter().find(|(a, _, _)| *a == self).map(|(_, _, desc)| *desc).unwrap_or("")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KeyChord {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    // shift is already part of uppercase chars and backtab, so it gets dropped to make "G" match shift+g
+    pub fn from_event(key: KeyEvent) -> Self {
+        let mut modifiers = key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+
+        if matches!(key.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+
+        KeyChord { code: key.code, modifiers }
+    }
+
+    // "ctrl-k", "alt-enter", "G", "?", "pageup"
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+
+        loop {
+            let lower = rest.to_lowercase();
+
+            if rest.len() > 1 && lower.starts_with("ctrl-") {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = &rest[5..];
+            } else if rest.len() > 1 && lower.starts_with("alt-") {
+                modifiers |= KeyModifiers::ALT;
+                rest = &rest[4..];
+            } else if rest.len() > 1 && lower.starts_with("shift-") {
+                modifiers |= KeyModifiers::SHIFT;
+                rest = &rest[6..];
+            } else {
+                break;
+            }
+        }
+
+        let code = match rest.to_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "space" => KeyCode::Char(' '),
+            f if f.len() > 1 && f.starts_with('f') && f[1..].parse::<u8>().is_ok() => {
+                KeyCode::F(f[1..].parse().unwrap())
+            }
+            _ => {
+                let mut chars = rest.chars();
+
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => {
+                        // shift-g is the same as G
+                        if modifiers.contains(KeyModifiers::SHIFT) {
+                            modifiers.remove(KeyModifiers::SHIFT);
+                            KeyCode::Char(c.to_ascii_uppercase())
+                        } else {
+                            KeyCode::Char(c)
+                        }
+                    }
+                    _ => return Err(format!("unknown key \"{}\"", text)),
+                }
+            }
+        };
+
+        if code == KeyCode::BackTab {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+
+        Ok(KeyChord { code, modifiers })
+    }
+
+    pub fn display(&self) -> String {
+        let mut out = String::new();
+
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            out.push_str("ctrl-");
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            out.push_str("alt-");
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            out.push_str("shift-");
+        }
+
+        let name = match self.code {
+            KeyCode::Enter => "enter",
+            KeyCode::Esc => "esc",
+            KeyCode::Tab => "tab",
+            KeyCode::BackTab => "backtab",
+            KeyCode::Backspace => "backspace",
+            KeyCode::Delete => "delete",
+            KeyCode::Insert => "insert",
+            KeyCode::Up => "up",
+            KeyCode::Down => "down",
+            KeyCode::Left => "left",
+            KeyCode::Right => "right",
+            KeyCode::Home => "home",
+            KeyCode::End => "end",
+            KeyCode::PageUp => "pageup",
+            KeyCode::PageDown => "pagedown",
+            KeyCode::Char(' ') => "space",
+            KeyCode::Char(c) => {
+                out.push(c);
+                ""
+            }
+            KeyCode::F(n) => {
+                out.push_str(&format!("f{}", n));
+                ""
+            }
+            _ => "?",
+        };
+        out.push_str(name);
+
+        out
+    }
+}
+
+// a binding is a sequence of chords, "g g" is two presses of g
+pub type KeySequence = Vec<KeyChord>;
+
+pub fn parse_sequence(text: &str) -> Result<KeySequence, String> {
+    let sequence = text.split_whitespace()
+        .map(KeyChord::parse)
+        .collect::<Result<KeySequence, String>>()?;
+
+    if sequence.is_empty() {
+        return Err("empty key binding".to_string());
+    }
+
+    Ok(sequence)
+}
+
+pub fn display_sequence(sequence: &KeySequence) -> String {
+    sequence.iter().map(|chord| chord.display()).collect::<Vec<_>>().join(" ")
+}
+
+pub enum KeyResult {
+    Action(Action),
+    // the key started a multi key binding, wait for the next one
+    Pending,
+    // nothing bound, in the input this means the keys get typed
+    Unbound(KeySequence),
+}
+
+pub struct Keymap {
+    pub vim: bool,
+    bindings: HashMap<Context, Vec<(KeySequence, Action)>>,
+}
+
+impl Keymap {
+    fn empty(vim: bool) -> Self {
+        Keymap {
+            vim,
+            bindings: HashMap::new(),
+        }
+    }
+
+    // later binds of the same keys replace earlier ones
+    fn bind(&mut self, context: Context, keys: &str, action: Action) {
+        let sequence = parse_sequence(keys).unwrap();
+        self.bind_sequence(context, sequence, action);
+    }
+
+    fn bind_sequence(&mut self, context: Context, sequence: KeySequence, action: Action) {
+        let bindings = self.bindings.entry(context).or_default();
+        bindings.retain(|(existing, _)| *existing != sequence);
+
+        if action != Action::Unbound {
+            bindings.push((sequence, action));
+        }
+    }
+
+    // the composer works the same in both keymaps, opening $EDITOR from the conversation goes with it
+    fn bind_input_keys(&mut self) {
+        self.bind(Context::Input, "esc", Action::Back);
+        self.bind(Context::Input, "enter", Action::Send);
+        self.bind(Context::Input, "shift-enter", Action::Newline);
+        self.bind(Context::Input, "alt-enter", Action::Newline);
+        self.bind(Context::Input, "left", Action::CursorLeft);
+        self.bind(Context::Input, "right", Action::CursorRight);
+        self.bind(Context::Input, "ctrl-left", Action::WordLeft);
+        self.bind(Context::Input, "alt-b", Action::WordLeft);
+        self.bind(Context::Input, "ctrl-right", Action::WordRight);
+        self.bind(Context::Input, "alt-f", Action::WordRight);
+        self.bind(Context::Input, "home", Action::LineStart);
+        self.bind(Context::Input, "ctrl-a", Action::LineStart);
+        self.bind(Context::Input, "end", Action::LineEnd);
+        self.bind(Context::Input, "ctrl-e", Action::LineEnd);
+        self.bind(Context::Input, "up", Action::HistoryPrevious);
+        self.bind(Context::Input, "down", Action::HistoryNext);
+        self.bind(Context::Input, "backspace", Action::DeleteBackward);
+        self.bind(Context::Input, "delete", Action::DeleteForward);
+        self.bind(Context::Input, "ctrl-w", Action::DeleteWord);
+        self.bind(Context::Input, "alt-backspace", Action::DeleteWord);
+        self.bind(Context::Input, "ctrl-u", Action::DeleteLine);
+        self.bind(Context::Input, "tab", Action::Complete);
+
+        for context in [Context::Conversation, Context::Input] {
+            self.bind(context, "alt-e", Action::ExternalEditor);
+            self.bind(context, "ctrl-x ctrl-e", Action::ExternalEditor);
+        }
+
+        for context in Context::ALL {
+            self.bind(context, "ctrl-k", Action::Switcher);
+        }
+    }
+
+    pub fn default_keymap() -> Self {
+        let mut keymap = Keymap::empty(false);
+
+        for context in [Context::Sidebar, Context::Conversation] {
+            keymap.bind(context, "esc", Action::Back);
+            keymap.bind(context, "up", Action::Up);
+            keymap.bind(context, "down", Action::Down);
+            keymap.bind(context, "home", Action::Top);
+            keymap.bind(context, "end", Action::Bottom);
+            keymap.bind(context, "ctrl-f", Action::Search);
+            keymap.bind(context, "n", Action::SearchNext);
+            keymap.bind(context, "N", Action::SearchPrevious);
+            keymap.bind(context, "?", Action::Help);
+            keymap.bind(context, ">", Action::SidebarWider);
+            keymap.bind(context, "<", Action::SidebarNarrower);
+        }
+
+        keymap.bind(Context::Sidebar, "enter", Action::Select);
+        keymap.bind(Context::Sidebar, "pageup", Action::PageUp);
+        keymap.bind(Context::Sidebar, "pagedown", Action::PageDown);
+        keymap.bind(Context::Sidebar, "s", Action::ToggleSort);
+        keymap.bind(Context::Sidebar, "m", Action::CycleMute);
+        keymap.bind(Context::Sidebar, "a", Action::SwitchAccount);
+        keymap.bind(Context::Sidebar, "u", Action::ToggleUnified);
+        keymap.bind(Context::Sidebar, "d", Action::Devices);
+        keymap.bind(Context::Sidebar, "E", Action::Export);
+        keymap.bind(Context::Conversation, "E", Action::Export);
+
+        keymap.bind(Context::Conversation, "e", Action::FocusInput);
+
+        keymap.bind_input_keys();
+
+        keymap
+    }
+
+    pub fn vim_keymap() -> Self {
+        let mut keymap = Keymap::empty(true);
+
+        for context in [Context::Sidebar, Context::Conversation] {
+            keymap.bind(context, "esc", Action::Back);
+            keymap.bind(context, "q", Action::Back);
+            keymap.bind(context, "k", Action::Up);
+            keymap.bind(context, "up", Action::Up);
+            keymap.bind(context, "j", Action::Down);
+            keymap.bind(context, "down", Action::Down);
+            keymap.bind(context, "g g", Action::Top);
+            keymap.bind(context, "G", Action::Bottom);
+            keymap.bind(context, "/", Action::Search);
+            keymap.bind(context, "n", Action::SearchNext);
+            keymap.bind(context, "N", Action::SearchPrevious);
+            keymap.bind(context, ":", Action::CommandMode);
+            keymap.bind(context, "?", Action::Help);
+            keymap.bind(context, ">", Action::SidebarWider);
+            keymap.bind(context, "<", Action::SidebarNarrower);
+        }
+
+        keymap.bind(Context::Sidebar, "enter", Action::Select);
+        keymap.bind(Context::Sidebar, "l", Action::Select);
+        keymap.bind(Context::Sidebar, "pageup", Action::PageUp);
+        keymap.bind(Context::Sidebar, "ctrl-b", Action::PageUp);
+        keymap.bind(Context::Sidebar, "pagedown", Action::PageDown);
+        keymap.bind(Context::Sidebar, "ctrl-f", Action::PageDown);
+        keymap.bind(Context::Sidebar, "s", Action::ToggleSort);
+        keymap.bind(Context::Sidebar, "m", Action::CycleMute);
+        keymap.bind(Context::Sidebar, "a", Action::SwitchAccount);
+        keymap.bind(Context::Sidebar, "u", Action::ToggleUnified);
+        keymap.bind(Context::Sidebar, "d", Action::Devices);
+        keymap.bind(Context::Sidebar, "E", Action::Export);
+        keymap.bind(Context::Conversation, "E", Action::Export);
+
+        keymap.bind(Context::Conversation, "h", Action::Back);
+        keymap.bind(Context::Conversation, "i", Action::FocusInput);
+        keymap.bind(Context::Conversation, "a", Action::FocusInput);
+
+        keymap.bind_input_keys();
+
+        keymap
+    }
+
+    pub fn bindings(&self, context: Context) -> &[(KeySequence, Action)] {
+        self.bindings.get(&context).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+
+    // first binding for an action, for hints in the ui
+    pub fn key_for(&self, context: Context, action: Action) -> Option<String> {
+        self.bindings(context).iter()
+            .find(|(_, a)| *a == action)
+            .map(|(sequence, _)| display_sequence(sequence))
+    }
+
+    // pending holds the keys of an unfinished multi key binding between calls
+    pub fn handle(&self, context: Context, pending: &mut KeySequence, key: KeyEvent) -> KeyResult {
+        pending.push(KeyChord::from_event(key));
+
+        let bindings = self.bindings(context);
+
+        if let Some((_, action)) = bindings.iter().find(|(sequence, _)| sequence == pending) {
+            pending.clear();
+            return KeyResult::Action(*action);
+        }
+
+        if bindings.iter().any(|(sequence, _)| sequence.len() > pending.len() && sequence.starts_with(pending)) {
+            return KeyResult::Pending;
+        }
+
+        // a dead end sequence, the last key might still be a binding by itself
+        let keys = std::mem::take(pending);
+
+        if keys.len() > 1
+            && let Some((_, action)) = bindings.iter().find(|(sequence, _)| sequence[..] == keys[keys.len() - 1..]) {
+            return KeyResult::Action(*action);
+        }
+
+        KeyResult::Unbound(keys)
+    }
+}
+
+// picks the base keymap from the config and applies the overrides from [keys.<context>]
+pub fn load(config: &crate::config::Config) -> color_eyre::Result<()> {
+    let mut keymap = match config.keymap.as_str() {
+        "default" => Keymap::default_keymap(),
+        "vim" => Keymap::vim_keymap(),
+        other => return Err(color_eyre::eyre::eyre!(
+            "Invalid config file {}:\nunknown keymap \"{}\", expected \"default\" or \"vim\"",
+            crate::config::config_path().display(), other
+        )),
+    };
+
+    let mut errors = vec![];
+
+    for (context_name, overrides) in &config.keys {
+        let Some(context) = Context::ALL.iter().find(|c| c.name() == context_name) else {
+            errors.push(format!("[keys.{}]: unknown context, expected sidebar, conversation or input", context_name));
+            continue;
+        };
+
+        for (keys, action_name) in overrides {
+            let sequence = match parse_sequence(keys) {
+                Ok(sequence) => sequence,
+                Err(err) => {
+                    errors.push(format!("[keys.{}] \"{}\": {}", context_name, keys, err));
+                    continue;
+                }
+            };
+
+            match Action::from_name(action_name) {
+                Some(action) => keymap.bind_sequence(*context, sequence, action),
+                None => errors.push(format!("[keys.{}] \"{}\": unknown action \"{}\"", context_name, keys, action_name)),
+            }
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(color_eyre::eyre::eyre!(
+            "Invalid config file {}:\n{}",
+            crate::config::config_path().display(), errors.join("\n")
+        ));
+    }
+
+    let _ = KEYMAP.set(keymap);
+
+    Ok(())
+}
+
+pub fn get() -> &'static Keymap {
+    KEYMAP.get_or_init(Keymap::default_keymap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_modifiers() {
+        let none = KeyModifiers::NONE;
+        let ctrl = KeyModifiers::CONTROL;
+        let alt = KeyModifiers::ALT;
+        let shift = KeyModifiers::SHIFT;
+
+        let cases = [
+            ("k", Some((KeyCode::Char('k'), none))),
+            ("G", Some((KeyCode::Char('G'), none))),
+            // shift on a char is the uppercase char, the way terminals send it
+            ("shift-g", Some((KeyCode::Char('G'), none))),
+            ("ctrl-k", Some((KeyCode::Char('k'), ctrl))),
+            ("CTRL-k", Some((KeyCode::Char('k'), ctrl))),
+            ("ctrl-alt-x", Some((KeyCode::Char('x'), ctrl | alt))),
+            ("alt-ctrl-x", Some((KeyCode::Char('x'), ctrl | alt))),
+            ("alt-enter", Some((KeyCode::Enter, alt))),
+            ("shift-enter", Some((KeyCode::Enter, shift))),
+            ("shift-backtab", Some((KeyCode::BackTab, none))),
+            ("ctrl-PageUp", Some((KeyCode::PageUp, ctrl))),
+            ("f5", Some((KeyCode::F(5), none))),
+            ("space", Some((KeyCode::Char(' '), none))),
+            ("-", Some((KeyCode::Char('-'), none))),
+            ("ctrl--", Some((KeyCode::Char('-'), ctrl))),
+            ("", None),
+            ("ctrl-", None),
+            ("ctrl-kk", None),
+            ("hyper-k", None),
+        ];
+
+        for (text, expected) in cases {
+            let parsed = KeyChord::parse(text).ok().map(|chord| (chord.code, chord.modifiers));
+            assert_eq!(parsed, expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parsed_chords_match_events() {
+        let cases = [
+            ("G", KeyCode::Char('G'), KeyModifiers::SHIFT),
+            ("ctrl-k", KeyCode::Char('k'), KeyModifiers::CONTROL),
+            ("shift-enter", KeyCode::Enter, KeyModifiers::SHIFT),
+            ("backtab", KeyCode::BackTab, KeyModifiers::SHIFT),
+        ];
+
+        for (text, code, modifiers) in cases {
+            let event = KeyChord::from_event(KeyEvent::new(code, modifiers));
+            assert_eq!(KeyChord::parse(text), Ok(event), "{:?}", text);
+        }
+    }
+}
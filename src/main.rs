@@ -1,85 +1,149 @@
-use std::io::Write;
-
 use color_eyre::Result;
 use crossterm::event::{self, Event};
-use qrcode::QrCode;
 use ratatui::{
-    layout::{Alignment, Constraint, Direction, Layout, Rect}, 
-    style::{Color, Style}, 
+    layout::{Alignment, Constraint, Direction, Layout}, 
+    style::Style, 
     text::Text, widgets::{Block, BorderType, Borders, Paragraph}, 
     DefaultTerminal
 };
-use signal::create_cli;
-use rusqlite::Connection;
-use directories::ProjectDirs;
 
 mod signal;
 mod db;
 mod types;
 mod app;
+mod config;
+mod notify;
+mod theme;
+mod keymap;
+mod editor;
+mod commands;
+mod fuzzy;
+mod ui;
+mod install;
+mod java;
+mod transport;
+mod register;
+mod accounts;
+mod cli;
+mod export;
+mod import;
+mod passphrase;
+mod backup;
 
 fn main() -> Result<()> {
 
     color_eyre::install()?;
-    
+    config::load()?;
+
+    // subcommands are for scripts and never touch the terminal
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(args);
+    }
+
+    theme::load(&config::get().theme)?;
+    keymap::load(config::get())?;
+
     let terminal = ratatui::init();
     let result = run(terminal);
-    ratatui::restore();
+    restore_terminal();
 
     result
 }
 
 
 fn run(mut terminal: DefaultTerminal) -> Result<()> {
-    let path = ProjectDirs::from(
-        "dev", 
-        "cyteon", 
-        "signal-tui"
-    ).map(|proj_dirs| {
-        proj_dirs.data_local_dir().to_path_buf()
-    }).unwrap();
+    let config = config::get();
+    let path = config.data_dir();
     std::fs::create_dir_all(&path)?;
 
     let _ = std::fs::remove_file(path.join("debug.log"));
 
-    let database = Connection::open(path.join("data.db"))?;
+    db::mark_in_use()?;
+
+    // before anything else, the account picker already shows names from it
+    if db::is_encrypted() && !passphrase::unlock(&mut terminal)? {
+        return Ok(());
+    }
+
+    let mut database = db::open()?;
     db::init(&database)?;
 
-    if !std::fs::exists(path.join("signal-cli/bin/signal-cli")).unwrap() {
-        signal::download_cli(&mut terminal, path.clone()).unwrap();
+    // a daemon brings its own signal-cli and java
+    let daemon = config.transport.is_daemon();
+
+    if !daemon {
+        let native = match java::preflight(&mut terminal)? {
+            java::Preflight::Ready => false,
+            java::Preflight::Native => true,
+            java::Preflight::Quit => return Ok(()),
+        };
+
+        install::ensure_installed(&mut terminal, native)?;
     }
 
-    let mut cli = create_cli(path.clone(), "".to_string()).unwrap();
-    let stdin = std::sync::Arc::new(std::sync::Mutex::new(cli.stdin.take().unwrap()));
-    let stdout: std::sync::Arc<std::sync::Mutex<std::process::ChildStdout>> = std::sync::Arc::new(std::sync::Mutex::new(cli.stdout.take().unwrap()));
+    let connection = transport::connect()?;
+    let mut process = connection.process;
+    let stdin = std::sync::Arc::new(std::sync::Mutex::new(connection.writer));
+    let stdout = std::sync::Arc::new(std::sync::Mutex::new(connection.reader));
+
+    let version = signal::version(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap());
+
+    match install::check_version(&mut terminal, version.as_deref())? {
+        install::VersionChoice::Continue => {}
+        install::VersionChoice::Quit => {
+            process.stop();
+            return Ok(());
+        }
+        install::VersionChoice::Upgrade => {
+            // the old one has to be stopped before its files get moved
+            process.stop();
+
+            let old_version = version.unwrap_or_default();
+            if let Err(err) = install::upgrade(&mut terminal, &old_version, &config.signal_cli.version) {
+                install::show_error(&mut terminal, "Upgrade failed", &err.to_string())?;
+            }
 
-    let mut accounts = signal::list_accounts(&mut *stdin.lock().unwrap(), &mut *stdout.lock().unwrap());
-    let mut selected_number = String::new();
+            let connection = transport::connect()?;
+            process = connection.process;
+            *stdin.lock().unwrap() = connection.writer;
+            *stdout.lock().unwrap() = connection.reader;
+        }
+    }
+
+    let mut accounts = match signal::list_accounts(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap()) {
+        Ok(accounts) => accounts,
+        Err(err) => {
+            install::show_error(&mut terminal, "signal-cli is not working", &format!("It could not list the linked accounts: {}", err))?;
+            process.stop();
+            return Ok(());
+        }
+    };
+    let mut infos = accounts::infos(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &database, &accounts);
     let mut index = 0;
 
+    // skip the picker if the default account from the config is linked
+    let mut auto_select = false;
+    if let Some(default_account) = &config.default_account
+        && let Some(i) = accounts.iter().position(|account| &account.number == default_account) {
+        index = i;
+        auto_select = true;
+    }
+
+    // shown below the accounts, index accounts.len() + i
+    let actions = ["Link Device", "Register Number"];
+
     loop {
         terminal.draw(|frame| {
-            let centered = {
-                let r = frame.area();
-                let width = r.width.min(60).max(20);
-                let height = (accounts.len() as u16 + 5).min(r.height.max(10));
-
-                let h_margin = (r.width - width) / 2;
-                let v_margin = (r.height - height) / 2;
-
-                Rect::new(
-                    h_margin,
-                    v_margin,
-                    width,
-                    height
-                )
-            };
+            // +2 for the hint line and the gap above it
+            let centered = ui::centered_rect(frame.area(), 70, (accounts.len() + actions.len()) as u16 + 6);
 
             let block = Block::default()
                 .title("Select Account")
                 .borders(Borders::ALL)
                 .title_alignment(Alignment::Center)
-                .border_type(BorderType::Rounded);
+                .border_type(BorderType::Rounded)
+                .border_style(theme::get().borders.style());
 
             frame.render_widget(block.clone(), centered);
 
@@ -90,20 +154,22 @@ fn run(mut terminal: DefaultTerminal) -> Result<()> {
                 .margin(1)
                 .constraints(
                     vec![
-                        Constraint::Length(1); accounts.len() + 1
+                        Constraint::Length(1); accounts.len() + actions.len() + 2
                     ]
                 )
                 .split(inner);
 
             for (i, account) in accounts.iter().enumerate() {
+                let label = infos[i].label(&account.number);
+
                 let text = if i == index {
-                    format!("> {} <", account.number)
+                    format!("> {} <", label)
                 } else {
-                    format!(" {} ", account.number)
+                    format!(" {} ", label)
                 };
 
                 let style = if i == index {
-                    Style::default().bg(Color::Blue)
+                    theme::get().selection.style()
                 } else {
                     Style::default()
                 };
@@ -115,194 +181,244 @@ fn run(mut terminal: DefaultTerminal) -> Result<()> {
                 frame.render_widget(paragraph, chunks[i]);
             }
 
-            let text = if index == accounts.len() {
-                format!("> Link Device <")
-            } else {
-                format!(" Link Device ")
-            };
+            for (i, action) in actions.iter().enumerate() {
+                let selected = index == accounts.len() + i;
 
-            let style = if index == accounts.len() {
-                Style::default().bg(Color::Blue)
-            } else {
-                Style::default()
-            };
+                let text = if selected {
+                    format!("> {} <", action)
+                } else {
+                    format!(" {} ", action)
+                };
 
-            let paragraph = Paragraph::new(text)
-                .style(style)
-                .alignment(Alignment::Center);
+                let style = if selected {
+                    theme::get().selection.style()
+                } else {
+                    Style::default()
+                };
+
+                let paragraph = Paragraph::new(text)
+                    .style(style)
+                    .alignment(Alignment::Center);
 
-            frame.render_widget(paragraph, chunks[accounts.len()]);
+                frame.render_widget(paragraph, chunks[accounts.len() + i]);
+            }
+
+            let hint = if index < accounts.len() {
+                "enter - select | d - remove | p - passphrase | esc - quit"
+            } else {
+                "enter - select | p - passphrase | esc - quit"
+            };
+            frame.render_widget(
+                Paragraph::new(hint)
+                    .style(theme::get().system_message.style())
+                    .alignment(Alignment::Center),
+                chunks[accounts.len() + actions.len() + 1]
+            );
         })?;
 
-        if event::poll(std::time::Duration::from_millis(100))? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    crossterm::event::KeyCode::Esc => break,
+        let key_code = if auto_select {
+            auto_select = false;
+            Some(crossterm::event::KeyCode::Enter)
+        } else if event::poll(config.poll_interval())? {
+            match event::read()? {
+                Event::Key(key) => Some(key.code),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        if let Some(key_code) = key_code {
+            match key_code {
+                crossterm::event::KeyCode::Esc => break,
+
+                crossterm::event::KeyCode::Up => {
+                    if index > 0 {
+                        index -= 1;
+                    } else {
+                        index = accounts.len() + actions.len() - 1;
+                    }
+                }
 
-                    crossterm::event::KeyCode::Up => {
-                        if index > 0 {
-                            index -= 1;
-                        } else {
-                            index = accounts.len();
-                        }
+                crossterm::event::KeyCode::Down => {
+                    if index < accounts.len() + actions.len() - 1 {
+                        index += 1;
+                    } else {
+                        index = 0;
                     }
+                }
 
-                    crossterm::event::KeyCode::Down => {
-                        if index < accounts.len() {
-                            index += 1;
-                        } else {
-                            index = 0;
-                        }
+                crossterm::event::KeyCode::Char('d') | crossterm::event::KeyCode::Delete if index < accounts.len() => {
+                    let number = accounts[index].number.clone();
+
+                    if accounts::remove(&mut terminal, &mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &database, &number)? {
+                        reload_accounts(&mut terminal, &mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &mut accounts)?;
+                        infos = accounts::infos(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &database, &accounts);
+                        index = index.min(accounts.len());
                     }
+                }
 
-                    crossterm::event::KeyCode::Enter => {
-                        if index == accounts.len() {
-                            let link = signal::link_device(&mut *stdin.lock().unwrap(), &mut *stdout.lock().unwrap());
-                            let code = QrCode::new(link.clone()).unwrap();
-                            
-                            let qr = code.render::<image::Luma<u8>>()
-                                .quiet_zone(true)
-                                .module_dimensions(1,1)
-                                .build();
-
-                            let mut out = String::new();
-                            let width = qr.width() as usize;
-                            let height = qr.height() as usize;
-                        
-                            for y in (0..height).step_by(2) {
-                                for x in 0..width {
-                                    let top = qr.get_pixel(x as u32, y as u32)[0] < 128;
-                                    let bottom = if y + 1 < height {
-                                        qr.get_pixel(x as u32, (y + 1) as u32)[0] < 128
-                                    } else {
-                                        false
-                                    };
-                                    let ch = match (top, bottom) {
-                                        (true, true) => '█',
-                                        (true, false) => '▀',
-                                        (false, true) => '▄',
-                                        (false, false) => ' ',
-                                    };
-                                    out.push(ch);
-                                }
-                                out.push('\n');
+                crossterm::event::KeyCode::Char('p') => {
+                    passphrase::change(&mut terminal, &mut database)?;
+                }
+
+                crossterm::event::KeyCode::Enter => {
+                    if index == accounts.len() + 1 {
+                        let registered = register::register(&mut terminal, &mut stdin.lock().unwrap(), &mut stdout.lock().unwrap())?;
+
+                        if let Some(number) = registered {
+                            reload_accounts(&mut terminal, &mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &mut accounts)?;
+                            infos = accounts::infos(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &database, &accounts);
+                            index = accounts.iter().position(|account| account.number == number).unwrap_or(0);
+                        }
+                    } else if index == accounts.len() {
+                        let link = match signal::link_device(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap()) {
+                            Ok(link) => link,
+                            Err(err) => {
+                                install::show_error(&mut terminal, "Linking failed", &err)?;
+                                continue;
                             }
-                                                        
-                            terminal.clear()?;
-                            terminal.flush()?;
-
-                            let (tx, rx) = std::sync::mpsc::channel();
-                            let stdin_clone = stdin.clone();
-                            let stdout_clone = stdout.clone();
-
-                            let cloned = link.clone();
-
-                            std::thread::spawn(move || {
-                                signal::finish_link(&mut *stdin_clone.lock().unwrap(), &mut *stdout_clone.lock().unwrap(), cloned);
-                                tx.send(true).unwrap();
-                            });
-
-                            loop {
-                                terminal.draw(|f| {
-                                    let centered = {
-                                        let qr_width = 49u16;
-                                        let qr_height = (49u16) / 2;
-
-                                        let area = f.area();
-                                        let h_margin = (area.width.saturating_sub(qr_width + 4)) / 2;  // +4 for block borders/margin
-                                        let v_margin = (area.height.saturating_sub(qr_height + 4)) / 2;
-                                    
-                                        Rect::new(
-                                            h_margin,
-                                            v_margin,
-                                            qr_width + 4,
-                                            qr_height + 4
-                                        )
-                                    };
-
-                                    let block = Block::default()
-                                        .title("Link Device - Scan QR Code with Signal")
-                                        .title_alignment(Alignment::Center);
-
-                                    f.render_widget(block.clone(), centered);
-
-                                    let inner = block.inner(centered);
-
-                                    let chunks = Layout::default()
-                                        .constraints(
-                                            vec![
-                                                Constraint::Length(1),
-                                                Constraint::Min(10),
-                                            ]
-                                        )
-                                        .split(inner);
-
-                                    f.render_widget(
-                                        Text::from(out.clone()),
-                                        chunks[1]
-                                    );
-
-                                    f.render_widget(
-                                        Text::from("Not working? Press 'o' to open image in browser."),
-                                        chunks[0]
+                        };
+                        let out = ui::qr_text(&link);
+                                                    
+                        terminal.clear()?;
+                        terminal.flush()?;
+
+                        let (tx, rx) = std::sync::mpsc::channel();
+                        let stdin_clone = stdin.clone();
+                        let stdout_clone = stdout.clone();
+
+                        let cloned = link.clone();
+
+                        std::thread::spawn(move || {
+                            let linked = signal::finish_link(&mut stdin_clone.lock().unwrap(), &mut stdout_clone.lock().unwrap(), cloned);
+                            let _ = tx.send(linked);
+                        });
+
+                        loop {
+                            terminal.draw(|f| {
+                                let centered = {
+                                    let qr_width = 49u16;
+                                    let qr_height = (49u16) / 2;
+
+                                    // +4 for block borders/margin
+                                    ui::centered_rect(f.area(), qr_width + 4, qr_height + 4)
+                                };
+
+                                let block = Block::default()
+                                    .title("Link Device - Scan QR Code with Signal")
+                                    .title_alignment(Alignment::Center);
+
+                                f.render_widget(block.clone(), centered);
+
+                                let inner = block.inner(centered);
+
+                                let chunks = Layout::default()
+                                    .constraints(
+                                        vec![
+                                            Constraint::Length(1),
+                                            Constraint::Min(10),
+                                        ]
                                     )
-                                })?;
-
-                                if event::poll(std::time::Duration::from_millis(100))? {
-                                    if let Event::Key(key) = event::read()? {
-                                        if key.code == crossterm::event::KeyCode::Esc {
-                                            break;
-                                        }
-
-                                        if key.code == crossterm::event::KeyCode::Char('o') {
-                                            webbrowser::open(
-                                                format!(
-                                                    "https://api.qrserver.com/v1/create-qr-code/?size=500x500&data={}",
-                                                    urlencoding::encode(
-                                                        &link
-                                                    )
-                                                ).as_str()
-                                            ).unwrap();
-                                        }
-                                    }
+                                    .split(inner);
+
+                                f.render_widget(
+                                    Text::from(out.clone()),
+                                    chunks[1]
+                                );
+
+                                f.render_widget(
+                                    Text::from("Not working? Press 'o' to open image in browser."),
+                                    chunks[0]
+                                )
+                            })?;
+
+                            if event::poll(config.poll_interval())?
+                                && let Event::Key(key) = event::read()? {
+                                if key.code == crossterm::event::KeyCode::Esc {
+                                    break;
                                 }
 
-                                if rx.try_recv().is_ok() {
-                                    accounts = signal::list_accounts(&mut *stdin.lock().unwrap(), &mut *stdout.lock().unwrap());
-                                    break;
+                                if key.code == crossterm::event::KeyCode::Char('o')
+                                    && let Err(err) = webbrowser::open(
+                                        format!(
+                                            "https://api.qrserver.com/v1/create-qr-code/?size=500x500&data={}",
+                                            urlencoding::encode(
+                                                &link
+                                            )
+                                        ).as_str()
+                                    ) {
+                                    install::show_error(&mut terminal, "Opening the browser failed", &err.to_string())?;
+                                }
+                            }
+
+                            if let Ok(linked) = rx.try_recv() {
+                                if let Err(err) = linked {
+                                    install::show_error(&mut terminal, "Linking failed", &err)?;
                                 }
 
-                                std::thread::sleep(std::time::Duration::from_millis(100));
+                                reload_accounts(&mut terminal, &mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &mut accounts)?;
+                                infos = accounts::infos(&mut stdin.lock().unwrap(), &mut stdout.lock().unwrap(), &database, &accounts);
+                                break;
                             }
-                        } else {
-                            selected_number = accounts[index].number.clone();
-
-                            cli.kill().unwrap();
-                            cli = create_cli(path.clone(), format!(
-                                "-a {}", selected_number
-                            )).unwrap();
-
-                            *stdin.lock().unwrap() = cli.stdin.take().unwrap();
-                            *stdout.lock().unwrap() = cli.stdout.take().unwrap();
-
-                            // all stff before was just starting the app, now we do the actual app in another file
-                            app::app(
-                                &mut terminal, 
-                                &mut *stdin.lock().unwrap(), 
-                                stdout,
-                                selected_number
-                            ).unwrap();
-
-                            break;
+
+                            std::thread::sleep(config.poll_interval());
                         }
+                    } else {
+                        let selected_number = accounts[index].number.clone();
+
+                        // signal-cli serves every account at once, requests just name the one to use
+                        stdin.lock().unwrap().account = Some(selected_number.clone());
+
+                        // all stff before was just starting the app, now we do the actual app in another file
+                        app::app(
+                            &mut terminal, 
+                            &mut stdin.lock().unwrap(), 
+                            stdout,
+                            accounts.iter().map(|account| account.number.clone()).collect(),
+                            selected_number
+                        )?;
+
+                        break;
                     }
-
-                    _ => {}
                 }
+
+                _ => {}
             }
         }
     }
 
+    process.stop();
+
+    Ok(())
+}
+
+// the picker keeps showing the accounts it had if signal-cli cant list them
+fn reload_accounts(
+    terminal: &mut DefaultTerminal,
+    stdin: &mut transport::Writer,
+    stdout: &mut transport::Reader,
+    accounts: &mut Vec<types::SignalAccount>,
+) -> Result<()> {
+    match signal::list_accounts(stdin, stdout) {
+        Ok(list) => *accounts = list,
+        Err(err) => install::show_error(terminal, "Listing accounts failed", &err)?,
+    }
+
+    Ok(())
+}
+
+// hands the terminal back, used on exit and to suspend the ui while another program runs
+pub fn restore_terminal() {
+    ratatui::restore();
+}
+
+// the terminal setup from ratatui::init again, after restore_terminal
+pub fn resume_terminal(terminal: &mut DefaultTerminal) -> Result<()> {
+    crossterm::terminal::enable_raw_mode()?;
+    crossterm::execute!(std::io::stdout(), crossterm::terminal::EnterAlternateScreen)?;
+    terminal.clear()?;
+
     Ok(())
 }
 
@@ -311,13 +427,7 @@ pub fn debug_to_file(
 ) {
     use std::io::Write;
 
-    let path = ProjectDirs::from(
-        "dev", 
-        "cyteon", 
-        "signal-tui"
-    ).map(|proj_dirs| {
-        proj_dirs.data_local_dir().to_path_buf()
-    }).unwrap();
+    let path = config::get().data_dir();
 
     std::fs::create_dir_all(&path).unwrap();
     let mut file = std::fs::OpenOptions::new()
@@ -0,0 +1,165 @@
+use std::io::Write;
+use std::process::Command;
+
+use serde::Deserialize;
+
+use crate::types;
+
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    // ring the terminal bell
+    pub bell: bool,
+    // OSC 9 and OSC 777 desktop notifications, supported by most modern terminals
+    pub osc: bool,
+    // external command, ran with the title and body as the last two arguments, eg. "notify-send"
+    pub command: Option<String>,
+    // show the message text, otherwise only "New message"
+    pub previews: bool,
+    // unread count in the window title
+    pub window_title: bool,
+    // default level for groups that dont have one set
+    pub mentions_only_groups: bool,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        NotificationSettings {
+            bell: true,
+            osc: true,
+            command: None,
+            previews: true,
+            window_title: true,
+            mentions_only_groups: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NotificationLevel {
+    All,
+    MentionsOnly,
+    Muted,
+}
+
+impl NotificationLevel {
+    pub fn from_i64(value: i64) -> Self {
+        match value {
+            1 => NotificationLevel::MentionsOnly,
+            2 => NotificationLevel::Muted,
+            _ => NotificationLevel::All,
+        }
+    }
+
+    pub fn to_i64(self) -> i64 {
+        match self {
+            NotificationLevel::All => 0,
+            NotificationLevel::MentionsOnly => 1,
+            NotificationLevel::Muted => 2,
+        }
+    }
+
+    // mentions only makes no sense in dms, so those skip it
+    pub fn next(self, is_group: bool) -> Self {
+        match self {
+            NotificationLevel::All if is_group => NotificationLevel::MentionsOnly,
+            NotificationLevel::All => NotificationLevel::Muted,
+            NotificationLevel::MentionsOnly => NotificationLevel::Muted,
+            NotificationLevel::Muted => NotificationLevel::All,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            NotificationLevel::All => "",
+            NotificationLevel::MentionsOnly => " [@]",
+            NotificationLevel::Muted => " [m]",
+        }
+    }
+}
+
+pub fn should_notify(
+    level: NotificationLevel,
+    incoming: &types::IncomingMessage,
+    account_number: &str,
+    own_uuid: Option<&str>,
+) -> bool {
+    match level {
+        NotificationLevel::All => true,
+        NotificationLevel::Muted => false,
+        NotificationLevel::MentionsOnly => incoming.mentions.iter().any(|mention| {
+            mention.number.as_deref() == Some(account_number)
+                || (own_uuid.is_some() && mention.uuid.as_deref() == own_uuid)
+        }),
+    }
+}
+
+pub fn notify(settings: &NotificationSettings, title: &str, body: &str) {
+    let body = if settings.previews { body } else { "New message" };
+
+    // escape sequences cant contain control characters, and ; splits the osc 777 fields
+    let clean = |s: &str| s.chars().filter(|c| !c.is_control()).collect::<String>();
+    let title = clean(title);
+    let body = clean(body);
+
+    let mut out = String::new();
+
+    if settings.bell {
+        out.push('\x07');
+    }
+
+    if settings.osc {
+        out.push_str(&passthrough(&format!("\x1b]9;{}: {}\x07", title, body)));
+        out.push_str(&passthrough(&format!(
+            "\x1b]777;notify;{};{}\x07",
+            title.replace(';', ","),
+            body.replace(';', ",")
+        )));
+    }
+
+    let mut stdout = std::io::stdout();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+
+    if let Some(command) = &settings.command {
+        let mut parts = command.split_whitespace();
+
+        if let Some(program) = parts.next() {
+            let child = Command::new(program)
+                .args(parts)
+                .arg(&title)
+                .arg(&body)
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .spawn();
+
+            match child {
+                // reap it in the background so we dont leave zombies around
+                Ok(mut child) => {
+                    std::thread::spawn(move || child.wait());
+                }
+                Err(err) => crate::debug_to_file(format!("Failed to run notification command: {}", err)),
+            }
+        }
+    }
+}
+
+pub fn set_window_title(unread: usize) {
+    let title = if unread > 0 {
+        format!("signal-tui ({})", unread)
+    } else {
+        "signal-tui".to_string()
+    };
+
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::terminal::SetTitle(title));
+}
+
+// tmux swallows osc sequences unless they are wrapped in its passthrough sequence
+fn passthrough(sequence: &str) -> String {
+    if std::env::var_os("TMUX").is_some() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence.to_string()
+    }
+}
@@ -0,0 +1,95 @@
+use color_eyre::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::DefaultTerminal;
+use rusqlite::Connection;
+
+use crate::{db, install, ui};
+
+// asks for the passphrase of an encrypted data.db until it opens, false if they gave up
+pub fn unlock(terminal: &mut DefaultTerminal) -> Result<bool> {
+    let mut message = "Your messages are encrypted.\nEnter the passphrase to open them.".to_string();
+
+    loop {
+        let Some(passphrase) = ui::prompt(terminal, "Unlock", &message, true)? else {
+            return Ok(false);
+        };
+
+        if passphrase.is_empty() {
+            continue;
+        }
+
+        install::draw_message(terminal, "Unlock", "Unlocking...")?;
+
+        if db::unlock(&passphrase)? {
+            return Ok(true);
+        }
+
+        message = "That passphrase is wrong.\nEnter the passphrase to open your messages.".to_string();
+    }
+}
+
+// sets, changes or removes the passphrase, database is reopened afterwards. true if it changed
+pub fn change(terminal: &mut DefaultTerminal, database: &mut Connection) -> Result<bool> {
+    if !db::cipher_available() {
+        install::show_error(terminal, "Passphrase", "This signal-tui was built without the encryption feature,\nso messages can not be encrypted.")?;
+        return Ok(false);
+    }
+
+    let encrypted = db::is_encrypted();
+    let message = if encrypted {
+        "Your messages are encrypted.\nEnter a new passphrase, or leave it empty to store them unencrypted."
+    } else {
+        "Your messages are stored unencrypted.\nEnter a passphrase to encrypt them, it is asked for on every start.\nThere is no way to recover it if it gets lost."
+    };
+
+    let Some(passphrase) = ui::prompt(terminal, "Passphrase", message, true)? else {
+        return Ok(false);
+    };
+
+    if passphrase.is_empty() {
+        if !encrypted || !confirm(terminal, "Really store your messages unencrypted?\n\n'y' - yes | esc - cancel")? {
+            return Ok(false);
+        }
+    } else {
+        let Some(again) = ui::prompt(terminal, "Passphrase", "Enter the passphrase again.", true)? else {
+            return Ok(false);
+        };
+
+        if again != passphrase {
+            install::show_error(terminal, "Passphrase", "The passphrases do not match, nothing was changed.")?;
+            return Ok(false);
+        }
+    }
+
+    install::draw_message(terminal, "Passphrase", if passphrase.is_empty() { "Decrypting..." } else { "Encrypting..." })?;
+
+    // the file gets replaced, so our connection cant keep pointing at the old one
+    *database = Connection::open_in_memory()?;
+    let result = db::change_passphrase(Some(passphrase.as_str()).filter(|passphrase| !passphrase.is_empty()));
+    *database = db::open()?;
+
+    if let Err(err) = result {
+        install::show_error(terminal, "Changing the passphrase failed", &format!("{:#}", err))?;
+        return Ok(false);
+    }
+
+    Ok(true)
+}
+
+fn confirm(terminal: &mut DefaultTerminal, message: &str) -> Result<bool> {
+    loop {
+        install::draw_message(terminal, "Passphrase", message)?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('y') => return Ok(true),
+                KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
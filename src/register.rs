@@ -0,0 +1,160 @@
+use color_eyre::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::DefaultTerminal;
+
+use crate::{install, signal, transport, ui};
+
+const CAPTCHA_URL: &str = "https://signalcaptchas.org/registration/generate.html";
+
+// signal-cli asks for a captcha with "Captcha required for verification ..."
+fn needs_captcha(message: &str) -> bool {
+    message.to_lowercase().contains("captcha")
+}
+
+// "Verification failed! This number is locked with a pin. ..."
+fn needs_pin(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("locked with a pin") || message.contains("registration lock")
+}
+
+// numbers go to signal with their country code, eg. +4915112345678
+fn valid_number(number: &str) -> bool {
+    number.len() > 4 && number.starts_with('+') && number[1..].chars().all(|c| c.is_ascii_digit())
+}
+
+// registers a number as a primary device, like the phone app does. Some(number) once it is verified
+pub fn register(
+    terminal: &mut DefaultTerminal,
+    stdin: &mut transport::Writer,
+    stdout: &mut transport::Reader,
+) -> Result<Option<String>> {
+    let mut message = "The number to register, with its country code, eg. +4915112345678.\nIt can not be used with the Signal app on a phone at the same time.".to_string();
+
+    let number = loop {
+        let Some(number) = ui::prompt(terminal, "Register Number", &message, false)? else {
+            return Ok(None);
+        };
+
+        let number: String = number.chars().filter(|c| !c.is_whitespace()).collect();
+        if valid_number(&number) {
+            break number;
+        }
+
+        message = format!("{} is not a valid number, it has to start with + and the country code.", number);
+    };
+
+    let Some(voice) = choose_method(terminal, &number)? else {
+        return Ok(None);
+    };
+
+    let mut captcha: Option<String> = None;
+
+    loop {
+        install::draw_message(terminal, "Register Number", &format!("Requesting a code for {}...", number))?;
+
+        let mut params = serde_json::json!({ "account": number, "voice": voice });
+        if let Some(captcha) = &captcha {
+            params["captcha"] = serde_json::Value::String(captcha.clone());
+        }
+
+        match signal::call(stdin, stdout, "register", params) {
+            Ok(_) => break,
+            Err(err) if needs_captcha(&err) => {
+                let message = format!(
+                    "Signal wants a captcha before it sends a code.\n\n1. Open {}\n2. Solve the captcha\n3. Right click \"Open Signal\", copy the link and paste it here\n\nThe link starts with signalcaptcha://",
+                    CAPTCHA_URL
+                );
+
+                match ui::prompt(terminal, "Captcha", &message, false)? {
+                    Some(token) if !token.is_empty() => captcha = Some(token),
+                    _ => return Ok(None),
+                }
+            }
+            Err(err) => {
+                install::show_error(terminal, "Registration failed", &err)?;
+                return Ok(None);
+            }
+        }
+    }
+
+    verify(terminal, stdin, stdout, &number, voice)
+}
+
+// Some(true) for a voice call, Some(false) for sms
+fn choose_method(terminal: &mut DefaultTerminal, number: &str) -> Result<Option<bool>> {
+    let message = format!(
+        "How should Signal send the code for {}?\n\n's' - SMS | 'v' - voice call | esc - cancel\n\nVoice calls only work after an SMS was requested.",
+        number
+    );
+
+    loop {
+        install::draw_message(terminal, "Register Number", &message)?;
+
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('s') => return Ok(Some(false)),
+                KeyCode::Char('v') => return Ok(Some(true)),
+                KeyCode::Esc => return Ok(None),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn verify(
+    terminal: &mut DefaultTerminal,
+    stdin: &mut transport::Writer,
+    stdout: &mut transport::Reader,
+    number: &str,
+    voice: bool,
+) -> Result<Option<String>> {
+    let message = format!(
+        "Enter the code Signal {} {}.",
+        if voice { "read out in a call to" } else { "sent by SMS to" },
+        number
+    );
+
+    loop {
+        let Some(code) = ui::prompt(terminal, "Verify", &message, false)? else {
+            return Ok(None);
+        };
+
+        // the sms shows it as 123-456
+        let code: String = code.chars().filter(|c| c.is_ascii_digit()).collect();
+        if code.is_empty() {
+            continue;
+        }
+
+        let mut pin: Option<String> = None;
+
+        loop {
+            install::draw_message(terminal, "Verify", &format!("Verifying {}...", number))?;
+
+            let mut params = serde_json::json!({ "account": number, "verificationCode": code });
+            if let Some(pin) = &pin {
+                params["pin"] = serde_json::Value::String(pin.clone());
+            }
+
+            match signal::call(stdin, stdout, "verify", params) {
+                Ok(_) => return Ok(Some(number.to_string())),
+                Err(err) if pin.is_none() && needs_pin(&err) => {
+                    let message = "This number has a registration lock.\nEnter the Signal PIN that was set for it.";
+
+                    match ui::prompt(terminal, "Registration Lock", message, true)? {
+                        Some(entered) if !entered.is_empty() => pin = Some(entered),
+                        _ => return Ok(None),
+                    }
+                }
+                // a wrong code or pin, let them try again
+                Err(err) => {
+                    install::show_error(terminal, "Verification failed", &err)?;
+                    break;
+                }
+            }
+        }
+    }
+}
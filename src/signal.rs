@@ -1,25 +1,30 @@
-use std::{io::{self, BufRead, BufReader, Read, Write}, path::PathBuf, sync::mpsc, time::Duration};
-use color_eyre::owo_colors::OwoColorize;
-use directories::ProjectDirs;
+use std::io::{self, Write};
 use hostname::get;
 use random_string::generate;
-use ratatui::{layout::{Alignment, Constraint, Direction, Layout}, style::{Color, Style}, widgets::{Block, Borders, Gauge, Paragraph}, DefaultTerminal};
-use rusqlite::Connection;
 use std::process::{Command, Stdio};
-use reqwest::blocking::Client;
-use flate2::read::GzDecoder;
-use tar::Archive;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
 
-use crate::{db, types::{self, SignalAccount, SignalContact, SignalGroup}};
+use crate::{transport, types::{self, SignalAccount, SignalContact, SignalGroup}};
 
-pub fn create_cli(path: PathBuf, args: String) -> io::Result<std::process::Child> {
-    let cli_path = match std::env::consts::OS {
-        "windows" => path.join("signal-cli/bin/signal-cli.bat"),
-        _ => path.join("signal-cli/bin/signal-cli")
-    };
+// how long a request may take, starting the jvm alone can take a while
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+// finishLink only answers once the qr code was scanned
+const LINK_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+pub fn create_cli(args: Vec<String>) -> io::Result<std::process::Child> {
+    let config = crate::config::get();
+
+    let mut command = Command::new(config.signal_cli_path());
+
+    // the launcher script looks at JAVA_HOME first, so point it at the java preflight picked
+    if let Some(java_home) = crate::java::home() {
+        command.env("JAVA_HOME", java_home);
+    }
 
-    let child = Command::new(cli_path)
-        .args(args.split_whitespace())
+    let child = command
+        .args(&config.signal_cli.args)
+        .args(args)
         .arg("jsonRpc")
         .arg("--receive-mode=manual")
         .stdin(Stdio::piped())
@@ -30,278 +35,413 @@ pub fn create_cli(path: PathBuf, args: String) -> io::Result<std::process::Child
     Ok(child)
 }
 
-pub fn list_accounts(stdin: &mut std::process::ChildStdin, stdout: &mut std::process::ChildStdout) -> Vec<SignalAccount> {
-    let id = generate_id();
-    writeln!(stdin, "{{\"jsonrpc\":\"2.0\",\"method\":\"listAccounts\",\"params\":{{}},\"id\":\"{}\"}}", id).unwrap();
+pub fn list_accounts(stdin: &mut transport::Writer, stdout: &mut transport::Reader) -> Result<Vec<SignalAccount>, String> {
+    let result = call(stdin, stdout, "listAccounts", serde_json::json!({}))?;
+    serde_json::from_value(result).map_err(|err| err.to_string())
+}
 
-    let mut retry_attempts_until_error = 20;
-    let mut response= String::new();
+// None if signal-cli doesnt answer, list_accounts fails with the reason right after
+pub fn version(stdin: &mut transport::Writer, stdout: &mut transport::Reader) -> Option<String> {
+    let id = send_request(stdin, "version", serde_json::json!({}));
+    let response = read_response(stdout, &id, RESPONSE_TIMEOUT).ok()?;
 
-    while response.is_empty() || !response.contains(&id) {
-        response = read_res(stdout);
+    let data: types::SignalGenericResponse = serde_json::from_str(&response).ok()?;
+    data.result.get("version")?.as_str().map(|version| version.to_string())
+}
 
-        retry_attempts_until_error -= 1;
+// the line answering a request, skipping everything else that comes in meanwhile
+fn read_response(stdout: &mut transport::Reader, id: &str, timeout: Duration) -> Result<String, String> {
+    let deadline = Instant::now() + timeout;
 
-        if retry_attempts_until_error == 0 {
-            // i have no idea if this is a good idea buttttttt
-            panic!("Failed to get response from signal-cli\nThis means that signal-cli most likely crashed\nPlease ensure you have java installed as that is a requirement");
+    loop {
+        match stdout.read_line(Some(deadline.saturating_duration_since(Instant::now()))) {
+            Ok(line) if line.contains(id) => return Ok(line),
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => return Err("signal-cli did not answer in time".to_string()),
+            Err(RecvTimeoutError::Disconnected) => return Err("signal-cli stopped responding".to_string()),
         }
-
-        std::thread::sleep(std::time::Duration::from_millis(100));
     }
+}
+
+// the result is {"deviceLinkUri":"sgnl://linkdevice?uuid=X&pub_key=X"}
+pub fn link_device(stdin: &mut transport::Writer, stdout: &mut transport::Reader) -> Result<String, String> {
+    let result = call(stdin, stdout, "startLink", serde_json::json!({}))?;
 
-    let data: types::SignalAccountList = serde_json::from_str(&response).unwrap();
-    data.result.iter()
-        .map(|account| SignalAccount {
-            number: account.number.clone(),
-        })
-        .collect()
+    result["deviceLinkUri"].as_str()
+        .map(|uri| uri.to_string())
+        .ok_or_else(|| "signal-cli did not send a link".to_string())
 }
 
-pub fn read_res(stdout: &mut std::process::ChildStdout) -> String {
-    use std::io::{BufRead, BufReader};
-    
-    let mut reader = BufReader::new(stdout);
-    let mut line = String::new();
-    
-    match reader.read_line(&mut line) {
-        Ok(_) => line,
-        Err(e) => {
-            eprintln!("Error reading response: {}", e);
-            String::new()
-        }
-    }
+// signal-cli runs with all accounts, each one has to be subscribed on its own
+pub fn subscribe_receive(
+    stdin: &mut transport::Writer, 
+    account_number: &str,
+) {
+    send_request(stdin, "subscribeReceive", serde_json::json!({ "account": account_number }));
 }
 
-pub fn link_device(stdin: &mut std::process::ChildStdin, stdout: &mut std::process::ChildStdout) -> String {
+// fire and forget, errors come back through the reader thread with the returned id
+pub fn send_request(stdin: &mut transport::Writer, method: &str, mut params: serde_json::Value) -> String {
     let id = generate_id();
-    writeln!(stdin, "{{\"jsonrpc\":\"2.0\",\"method\":\"startLink\",\"id\":\"{}\"}}", id).unwrap();
 
-    let mut response = String::new();
-
-    while response.is_empty() || !response.contains(&id) {
-        response = read_res(stdout);
-        std::thread::sleep(std::time::Duration::from_millis(100));
+    if let Some(account) = &stdin.account
+        && params.get("account").is_none() {
+        params["account"] = serde_json::Value::String(account.clone());
     }
 
-    let data: types::SignalLinkingResponse = serde_json::from_str(&response).unwrap();
-    let link = data.result.get("deviceLinkUri").unwrap().clone();
+    let payload = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "id": id,
+        "params": params,
+    });
+
+    // a failed write means signal-cli is gone, whoever waits for the answer hears that from the reader
+    let _ = writeln!(stdin, "{}", payload);
 
-    link
+    id
 }
 
-pub fn subscribe_receive(
-    stdin: &mut std::process::ChildStdin, 
-) {
-    let id = generate_id();
-    writeln!(stdin, "{{\"jsonrpc\":\"2.0\",\"method\":\"subscribeReceive\",\"params\":{{}},\"id\":\"{}\"}}", id).unwrap();
+// sends a request and waits for its answer, Err is the message signal-cli sent back
+pub fn call(
+    stdin: &mut transport::Writer,
+    stdout: &mut transport::Reader,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<serde_json::Value, String> {
+    let id = send_request(stdin, method, params);
+    wait_for(stdout, &id)
+}
+
+// the answer to a request sent with send_request, skipping everything else that comes in meanwhile
+pub fn wait_for(stdout: &mut transport::Reader, id: &str) -> Result<serde_json::Value, String> {
+    wait_for_within(stdout, id, RESPONSE_TIMEOUT)
+}
+
+fn wait_for_within(stdout: &mut transport::Reader, id: &str, timeout: Duration) -> Result<serde_json::Value, String> {
+    let response = read_response(stdout, id, timeout)?;
+
+    if let Ok(data) = serde_json::from_str::<types::SignalErrorResponse>(&response) {
+        return Err(data.error.message);
+    }
+
+    let data: serde_json::Value = serde_json::from_str(&response).map_err(|err| err.to_string())?;
+    Ok(data["result"].clone())
+}
+
+// 0 = group, 1 = contact
+fn destination(dest_id: &str, dest_type: usize) -> serde_json::Value {
+    match dest_type {
+        0 => serde_json::json!({ "groupId": dest_id }),
+        1 => serde_json::json!({ "recipient": [dest_id] }),
+        _ => panic!("Invalid destination type"),
+    }
 }
 
 pub fn send_msg(
-    stdin: &mut std::process::ChildStdin, 
+    stdin: &mut transport::Writer, 
     msg: String,
     dest_id: String,
     dest_type: usize, // 0 = group, 1 = contact
     db: &rusqlite::Connection,
     account_number: String
 ) {
-    let id = generate_id();
+    send_msg_with(stdin, msg, serde_json::json!({}), dest_id, dest_type, db, account_number);
+}
+
+// extra is merged into the send params, eg. attachments or textStyle
+pub fn send_msg_with(
+    stdin: &mut transport::Writer,
+    msg: String,
+    extra: serde_json::Value,
+    dest_id: String,
+    dest_type: usize,
+    db: &rusqlite::Connection,
+    account_number: String
+) -> String {
+    let mut params = destination(&dest_id, dest_type);
+    params["message"] = serde_json::Value::String(msg.clone());
+
+    if let serde_json::Value::Object(extra) = &extra {
+        for (key, value) in extra {
+            params[key] = value.clone();
+        }
+    }
+
+    let id = send_request(stdin, "send", params);
+
+    // the chat only shows text, so leave a note of the attachments in it too
+    let mut stored = msg;
+    let mut attachments = vec![];
+    if let Some(paths) = extra["attachments"].as_array() {
+        for attachment in paths.iter().filter_map(|a| a.as_str()) {
+            let name = std::path::Path::new(attachment).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+            if !stored.is_empty() {
+                stored.push(' ');
+            }
+            stored.push_str(&format!("[attachment: {}]", name));
+
+            attachments.push(types::SignalAttachment {
+                filename: Some(name),
+                size: std::fs::metadata(attachment).ok().map(|metadata| metadata.len()),
+                path: Some(attachment.to_string()),
+                ..Default::default()
+            });
+        }
+    }
+    let attachments = (!attachments.is_empty()).then(|| serde_json::to_string(&attachments).unwrap());
 
-    let dest_payload = if dest_type == 0 {
-        format!(r#""groupId": "{}""#, dest_id)
-    } else if dest_type == 1 {
-        format!(r#""recipient": ["{}"]"#, dest_id)
+    let (destination_uuid, group_id) = if dest_type == 0 {
+        (None, Some(dest_id))
     } else {
-        panic!("Invalid destination type");
+        (Some(dest_id), None)
     };
 
-    let payload = format!(
-        r#"
-            {{
-                "jsonrpc":"2.0",
-                "method":"send",
-                "id":"{}",
-                "params": {{
-                    "message": "{}",
-                    {}
-                }}
-            }}
-        "#,
-        id, msg, dest_payload
-    );
-
-    let payload = payload.replace("\n", "");
-    let payload = payload.replace("\t", "");
-    let payload = payload.trim().to_string();
-
-    writeln!(
-        stdin, "{}", payload
+    db.execute(
+        "INSERT INTO messages (id, sourceUuid, sourceNumber, sourceName, destinationUuid, groupId, message, timestamp, pending, accountNumber, attachments) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        rusqlite::params![
+            id,
+            "self",
+            account_number,
+            "(you)",
+            destination_uuid,
+            group_id,
+            stored,
+            0,
+            1,
+            account_number,
+            attachments
+        ],
     ).unwrap();
 
+    id
+}
+
+// local nickname, only shown on our devices
+pub fn set_nickname(stdin: &mut transport::Writer, uuid: &str, name: &str) -> String {
+    send_request(stdin, "updateContact", serde_json::json!({
+        "recipient": uuid,
+        "nickGivenName": name,
+    }))
+}
+
+// disappearing messages timer in seconds, 0 turns it off
+pub fn set_expiration(stdin: &mut transport::Writer, dest_id: &str, dest_type: usize, seconds: u32) -> String {
     if dest_type == 0 {
-        db.execute(
-            "INSERT INTO messages (id, sourceUuid, sourceNumber, sourceName, destinationUuid, groupId, message, timestamp, pending, accountNumber) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
-            rusqlite::params![
-                id,
-                "self",
-                account_number,
-                "(you)",
-                None::<String>,
-                dest_id,
-                msg,
-                0,
-                1,
-                account_number
-            ],
-        ).unwrap();
+        send_request(stdin, "updateGroup", serde_json::json!({
+            "groupId": dest_id,
+            "expiration": seconds,
+        }))
     } else {
-        db.execute(
-            "INSERT INTO messages (id, sourceUuid, sourceNumber, sourceName, destinationUuid, groupId, message, timestamp, pending, accountNumber) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
-            rusqlite::params![
-                id,
-                "self",
-                account_number,
-                "(you)",
-                dest_id,
-                None::<String>,
-                msg,
-                0,
-                1,
-                account_number
-            ],
-        ).unwrap();
+        send_request(stdin, "updateContact", serde_json::json!({
+            "recipient": dest_id,
+            "expiration": seconds,
+        }))
     }
 }
 
-pub fn read_events_countinously(
-    stdout: &mut std::process::ChildStdout,
-) {
-    let path = ProjectDirs::from(
-        "dev", 
-        "cyteon", 
-        "signal-tui"
-    ).map(|proj_dirs| {
-        proj_dirs.data_local_dir().to_path_buf()
-    }).unwrap();
+pub fn set_blocked(stdin: &mut transport::Writer, dest_id: &str, dest_type: usize, blocked: bool) -> String {
+    let params = if dest_type == 0 {
+        serde_json::json!({ "groupId": [dest_id] })
+    } else {
+        serde_json::json!({ "recipient": [dest_id] })
+    };
+
+    send_request(stdin, if blocked { "block" } else { "unblock" }, params)
+}
+
+// name, description etc, params are the updateGroup options
+pub fn update_group(stdin: &mut transport::Writer, group_id: &str, mut params: serde_json::Value) -> String {
+    params["groupId"] = serde_json::Value::String(group_id.to_string());
+    send_request(stdin, "updateGroup", params)
+}
+
+pub fn quit_group(stdin: &mut transport::Writer, group_id: &str) -> String {
+    send_request(stdin, "quitGroup", serde_json::json!({ "groupId": group_id }))
+}
+
+// the answer comes back as a ReaderEvent::Response with the returned id
+pub fn list_devices(stdin: &mut transport::Writer) -> String {
+    send_request(stdin, "listDevices", serde_json::json!({}))
+}
+
+// only works from the primary device
+pub fn remove_device(stdin: &mut transport::Writer, device_id: u64) -> String {
+    send_request(stdin, "removeDevice", serde_json::json!({ "deviceId": device_id }))
+}
+
+// links the device showing the sgnl://linkdevice uri, only works from the primary device
+pub fn add_device(stdin: &mut transport::Writer, uri: &str) -> String {
+    send_request(stdin, "addDevice", serde_json::json!({ "uri": uri }))
+}
+
+// the name the other devices see for this one
+pub fn set_device_name(stdin: &mut transport::Writer, name: &str) -> String {
+    send_request(stdin, "updateAccount", serde_json::json!({ "deviceName": name }))
+}
+
+// stores a received message, None if there was nothing to store or it is stored already.
+// the importer uses this too, for signal-cli's receive --output=json logs
+pub fn store_envelope(
+    db: &rusqlite::Connection,
+    account_number: &str,
+    envelope: types::SignalMessageEventEnvelope,
+) -> rusqlite::Result<Option<types::IncomingMessage>> {
+    let source_uuid = envelope.source_uuid;
+    let source_name = envelope.source_name;
+    let timestamp = envelope.timestamp;
+
+    // data messages are from other people, sent messages are ours from other devices, both can carry these
+    let sent_message = envelope.sync_message.and_then(|sync_message| sync_message.sent_message);
+    let (quote, reaction, attachments) = match (&envelope.data_message, &sent_message) {
+        (Some(data_message), _) => (data_message.quote.clone(), data_message.reaction.clone(), data_message.attachments.clone()),
+        (None, Some(sent_message)) => (sent_message.quote.clone(), sent_message.reaction.clone(), sent_message.attachments.clone()),
+        _ => (None, None, vec![]),
+    };
+
+    // reactions belong to another message, they dont get a row of their own
+    if let Some(reaction) = reaction {
+        crate::db::save_reaction(db, account_number, &reaction, &source_uuid, &source_name)?;
+        return Ok(None);
+    }
+
+    let msg = match (&envelope.data_message, &sent_message) {
+        (Some(data_message), _) => data_message.message.clone().unwrap_or_default(),
+        (None, Some(sent_message)) => match &sent_message.message {
+            Some(message) => message.clone(),
+            None if !attachments.is_empty() => String::new(),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+
+    // 0 = group, 1 = contact
+    let (group_id, destination_uuid) = match (&sent_message, &envelope.data_message) {
+        (Some(sent_message), _) => match (&sent_message.destination_uuid, &sent_message.group_info) {
+            (Some(destination_uuid), _) => (None, Some(destination_uuid.clone())),
+            (None, Some(group_info)) => (Some(group_info.group_id.clone()), None),
+            (None, None) => return Ok(None),
+        },
+        (None, Some(data_message)) => match &data_message.group_info {
+            Some(group_info) => (Some(group_info.group_id.clone()), None),
+            None => (None, Some("self".to_string())),
+        },
+        (None, None) => return Ok(None),
+    };
+
+    let conversation_id = match (&group_id, destination_uuid.as_deref()) {
+        (Some(group_id), _) => group_id.clone(),
+        (None, Some("self")) | (None, None) => source_uuid.clone(),
+        (None, Some(destination_uuid)) => destination_uuid.to_string(),
+    };
+    let conversation_type = if group_id.is_some() { 0 } else { 1 };
 
-    let db: &rusqlite::Connection = &Connection::open(path.join("data.db")).unwrap();
+    // signal-cli can hand out a message twice and logs get imported more than once
+    if crate::db::has_message(db, account_number, conversation_type, &conversation_id, timestamp, &msg)? {
+        return Ok(None);
+    }
+
+    let source_number = envelope.source_number;
+
+    let (mentions, expires_in_seconds) = match (envelope.data_message, sent_message) {
+        (Some(data_message), _) => (data_message.mentions, data_message.expires_in_seconds),
+        (None, Some(sent_message)) => (vec![], sent_message.expires_in_seconds),
+        _ => (vec![], 0),
+    };
 
-    let reader = BufReader::new(stdout);
+    let attachments = (!attachments.is_empty()).then(|| serde_json::to_string(&attachments).unwrap());
+
+    db.execute(
+        "INSERT INTO messages (id, sourceUuid, sourceNumber, sourceName, destinationUuid, groupId, message, timestamp, expiresInSeconds, pending, accountNumber, quoteTimestamp, quoteAuthor, quoteText, attachments) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+        rusqlite::params![
+            generate_id(), // ill use this for msg ids too
+            source_uuid,
+            source_number,
+            source_name,
+            destination_uuid,
+            group_id,
+            msg,
+            timestamp,
+            expires_in_seconds,
+            0,
+            account_number,
+            quote.as_ref().map(|quote| quote.id),
+            quote.as_ref().and_then(|quote| quote.author.clone().or(quote.author_number.clone())),
+            quote.as_ref().and_then(|quote| quote.text.clone()),
+            attachments
+        ],
+    )?;
+
+    Ok(Some(types::IncomingMessage {
+        account_number: account_number.to_string(),
+        conversation_id,
+        group_id,
+        source_number,
+        source_name,
+        timestamp,
+        message: msg,
+        mentions,
+    }))
+}
 
-    for line in reader.lines() {
-        let line = line.unwrap_or_default();
-        crate::debug_to_file(line.clone());
+// runs until signal-cli closes its output, Ok then. lines it can not make sense of are skipped
+pub fn read_events_countinously(
+    stdout: &mut transport::Reader,
+    events: &std::sync::mpsc::Sender<types::ReaderEvent>,
+) -> Result<(), String> {
+    let db: &rusqlite::Connection = &crate::db::open().map_err(|err| err.to_string())?;
 
+    // lines are not logged, they hold message bodies that would end up next to an encrypted database in plain text
+    while let Ok(line) = stdout.read_line(None) {
         if line.contains("\"method\":\"receive\"") {
             let data: Result<types::SignalMessageEvent, serde_json::Error> = serde_json::from_str(&line);
 
             if let Ok(data) = data {
-                let envelope = data.params.result.envelope;
-
-                let source_uuid = envelope.source_uuid;
-                let source_name = envelope.source_name;
-                let timestamp = envelope.timestamp;
-                let account_number = data.params.result.account;
-
-                let msg = if let Some(data_message) = envelope.data_message.clone() {
-                    data_message.message.unwrap_or_default()
-                } else if let Some(sync_message) = envelope.sync_message.clone()  {
-                    if let Some(sent_message) = sync_message.sent_message {
-                        if let Some(message) = sent_message.message {
-                            message
-                        } else {
-                            return;
-                        }
-                    } else {
-                        return;
-                    }
-                } else {
-                    return;
-                };
+                let result = data.params.into_result();
 
-                let mut group_id = None;
-
-                let destionation_uuid = if let Some(sync_message) = envelope.sync_message.clone() {
-                    if let Some(sent_message) = sync_message.sent_message {
-                        if let Some(destination_uuid) = sent_message.destination_uuid { Some(destination_uuid) } else {
-                            if let Some(group_info) = sent_message.group_info {
-                                group_id = Some(group_info.group_id);
-                                None
-                            } else {
-                                return;
-                            }
-                        }
-                    } else {
-                        return;
+                let incoming = match store_envelope(db, &result.account, result.envelope) {
+                    Ok(Some(incoming)) => incoming,
+                    Ok(None) => {
+                        let _ = events.send(types::ReaderEvent::Changed);
+                        continue;
                     }
-                } else {
-                    if envelope.data_message.is_some() {
-                        if let Some(group_info) = envelope.data_message.clone().unwrap().group_info {
-                            group_id = Some(group_info.group_id);
-                            None
-                        } else {
-                            Some("self".to_string())
-                        }
-                    } else {
-                        return;
+                    Err(err) => {
+                        let _ = events.send(types::ReaderEvent::Error { id: None, message: format!("Storing a message failed: {}", err) });
+                        continue;
                     }
                 };
 
-                let source_number = if let Some(source_number) = envelope.source_number {
-                    Some(source_number)
-                } else {
-                    None
-                };
-
-                let expires_in_seconds = if let Some(data_message) = envelope.data_message {
-                    data_message.expires_in_seconds
-                } else if let Some(sync_message) = envelope.sync_message {
-                    if let Some(sent_message) = sync_message.sent_message {
-                        sent_message.expires_in_seconds
-                    } else {
-                        0
-                    }
+                // only other peoples messages notify, ours come as sync messages from our other devices
+                if !incoming.message.is_empty() && incoming.source_number.as_deref() != Some(result.account.as_str()) {
+                    let _ = events.send(types::ReaderEvent::Message(incoming));
                 } else {
-                    0
-                };
-
-                db.execute(
-                    "INSERT INTO messages (id, sourceUuid, sourceNumber, sourceName, destinationUuid, groupId, message, timestamp, expiresInSeconds, pending, accountNumber) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-                    rusqlite::params![
-                        generate_id(), // ill use this for msg ids too
-                        source_uuid,
-                        source_number,
-                        source_name,
-                        destionation_uuid,
-                        group_id,
-                        msg,
-                        timestamp,
-                        expires_in_seconds,
-                        0,
-                        account_number
-                    ],
-                ).unwrap();
-            } else {
-                return;
+                    let _ = events.send(types::ReaderEvent::Changed);
+                }
+            }
+        } else if line.contains("\"error\"") {
+            if let Ok(data) = serde_json::from_str::<types::SignalErrorResponse>(&line) {
+                let _ = events.send(types::ReaderEvent::Error {
+                    id: data.id,
+                    message: data.error.message,
+                });
             }
         } else if line.contains("\"type\":\"SUCCESS\"") {
             let data: Result<types::SignalGenericResponse, serde_json::Error> = serde_json::from_str(&line);
 
             match data {
                 Ok(data) => {
-                    if data.id.is_some() {
-                        let id = data.id.unwrap();
+                    if let Some(id) = data.id {
                         let timestamp = match data.result["timestamp"].clone() {
                             serde_json::Value::Number(num) => num.as_u64().unwrap(),
-                            _ => return
+                            _ => continue
                         };
                         
-                        db.execute(
-                            "UPDATE messages SET pending = 0, timestamp = ?1 WHERE id = ?2",
-                            rusqlite::params![timestamp, id]
-                        ).unwrap();
-                    } else {
-                        return;
+                        let event = match crate::db::mark_sent(db, &id, timestamp) {
+                            Ok(()) => types::ReaderEvent::Changed,
+                            Err(err) => types::ReaderEvent::Error { id: None, message: format!("Marking a message as sent failed: {}", err) },
+                        };
+                        let _ = events.send(event);
                     }
                 }
 
@@ -309,55 +449,38 @@ pub fn read_events_countinously(
                     crate::debug_to_file(
                         format!("Error parsing generic JSON for type success: {}", err)
                     );
-                    return;
                 }
             } 
+        } else if line.contains("\"result\"")
+            && let Ok(data) = serde_json::from_str::<serde_json::Value>(&line)
+            && let Some(id) = data["id"].as_str() {
+            let _ = events.send(types::ReaderEvent::Response {
+                id: id.to_string(),
+                result: data["result"].clone(),
+            });
         }
     }
+
+    Ok(())
 }
 
 
 pub fn finish_link(
-    stdin: &mut std::process::ChildStdin, 
-    stdout: &mut std::process::ChildStdout, 
+    stdin: &mut transport::Writer, 
+    stdout: &mut transport::Reader, 
     link: String
-) {
+) -> Result<(), String> {
     let name = match get() {
         Ok(name) => name.into_string().unwrap_or("Unknown".to_string()),
         Err(_) => "Unknown".to_string()
     };
 
-    let id = generate_id();
-
-    let mut content = format!(
-        r#"
-            {{
-                "jsonrpc":"2.0",
-                "method":"finishLink",
-                "id":"{}",
-                "params": {{
-                    "deviceLinkUri": "{}",
-                    "deviceName": "{}"
-                }}
-            }}
-        "#,
-        id, link, name
-    );
-
-    content = content.replace("\n", "");
-    content = content.replace("\t", "");
-    content = content.trim().to_string();
-
-    writeln!(
-        stdin, "{}", content
-    ).unwrap();
-
-    let mut response = String::new();
+    let id = send_request(stdin, "finishLink", serde_json::json!({
+        "deviceLinkUri": link,
+        "deviceName": name,
+    }));
 
-    while response.is_empty() || !response.contains(&id) {
-        response = read_res(stdout);
-        std::thread::sleep(std::time::Duration::from_millis(100));
-    }
+    wait_for_within(stdout, &id, LINK_TIMEOUT).map(|_| ())
 }
 
 pub fn generate_id() -> String {
@@ -366,108 +489,25 @@ pub fn generate_id() -> String {
 }
 
 pub fn sync(
-    stdin: &mut std::process::ChildStdin, 
-    stdout: &mut std::process::ChildStdout,
-) -> (Vec<SignalGroup>, Vec<SignalContact>) {
+    stdin: &mut transport::Writer, 
+    stdout: &mut transport::Reader,
+) -> Result<(Vec<SignalGroup>, Vec<SignalContact>), String> {
     // groups
 
-    let mut id = generate_id();
-    writeln!(stdin, "{{\"jsonrpc\":\"2.0\",\"method\":\"listGroups\",\"id\":\"{}\"}}", id).unwrap();
-
-    let mut response = String::new();
+    let mut id = send_request(stdin, "listGroups", serde_json::json!({}));
+    let response = read_response(stdout, &id, RESPONSE_TIMEOUT)?;
 
-    while response.is_empty() || !response.contains(&id) {
-        response = read_res(stdout);
-        std::thread::sleep(std::time::Duration::from_millis(100));
-    }
-
-    let data: types::SignalGroupList = serde_json::from_str(&response).unwrap();
+    let data: types::SignalGroupList = serde_json::from_str(&response).map_err(|err| err.to_string())?;
 
     let groups = data.result;
 
     // contacts
 
-    id = generate_id();
-    writeln!(stdin, "{{\"jsonrpc\":\"2.0\",\"method\":\"listContacts\",\"id\":\"{}\"}}", id).unwrap();
-
-    response = String::new();
-
-    while response.is_empty() || !response.contains(&id) {
-        response = read_res(stdout);
-        std::thread::sleep(std::time::Duration::from_millis(100));
-    }
+    id = send_request(stdin, "listContacts", serde_json::json!({}));
+    let response = read_response(stdout, &id, RESPONSE_TIMEOUT)?;
 
-    let data: types::SignalContactList = serde_json::from_str(&response).unwrap();
+    let data: types::SignalContactList = serde_json::from_str(&response).map_err(|err| err.to_string())?;
     let contacts = data.result;
 
-    (groups, contacts)
+    Ok((groups, contacts))
 }
-
-// cli download
-
-pub fn download_cli(terminal: &mut DefaultTerminal, path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    let url = "https://github.com/AsamK/signal-cli/releases/download/v0.13.14/signal-cli-0.13.14.tar.gz";
-    let download_path = path.join("signal-cli-0.13.14.tar.gz");
-    let mut file = std::fs::File::create(&download_path)?;
-
-    let client = Client::new();
-    let mut response = client.get(url).send()?;
-
-    let total_size = response.content_length().unwrap_or(0);
-
-    let mut downloaded: u64 = 0;
-    let mut buffer = [0; 8192];
-
-    loop {
-        let b = response.read(&mut buffer)?;
-
-        if b == 0 {
-            let tar_gz = GzDecoder::new(std::fs::File::open(&download_path)?);
-            let mut archive = Archive::new(tar_gz);
-            archive.unpack(&path)?;
-            std::fs::remove_file(&download_path)?;
-
-            std::fs::rename(
-                path.join("signal-cli-0.13.14"),
-                path.join("signal-cli")
-            )?;
-
-            break;
-        }
-
-        file.write_all(&buffer[..b])?;
-        downloaded += b as u64;
-
-        let percent = (downloaded as f64 / total_size as f64 * 100.0) as u16;
-
-        terminal.draw(|f| {
-            let chunks = Layout::default()
-                .direction(Direction::Vertical)
-                .margin(5)
-                .constraints(
-                    [
-                        Constraint::Length(1),
-                        Constraint::Length(3),
-                        Constraint::Min(0),
-                    ]
-                    .as_ref(),
-                )
-                .split(f.area());
-        
-            let title = Paragraph::new("Downloading signal-cli...")
-                .alignment(Alignment::Center);
-        
-            f.render_widget(title, chunks[0]);
-            
-            let gauge = Gauge::default()
-                .block(Block::default().borders(Borders::ALL))
-                .gauge_style(Style::default().fg(Color::Green))
-                .label(format!("{}%", percent))
-                .percent(percent);
-
-            f.render_widget(gauge, chunks[1]);
-        })?;
-    }
-
-    Ok(())
-}
\ No newline at end of file
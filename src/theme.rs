@@ -0,0 +1,164 @@
+use std::sync::OnceLock;
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleDef {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+    pub reversed: bool,
+}
+
+impl StyleDef {
+    fn new(fg: Option<Color>, bg: Option<Color>, bold: bool) -> Self {
+        StyleDef { fg, bg, bold, ..Default::default() }
+    }
+
+    pub fn style(&self) -> Style {
+        let mut style = Style::default();
+
+        if let Some(fg) = self.fg {
+            style = style.fg(fg);
+        }
+        if let Some(bg) = self.bg {
+            style = style.bg(bg);
+        }
+        if self.bold {
+            style = style.add_modifier(Modifier::BOLD);
+        }
+        if self.italic {
+            style = style.add_modifier(Modifier::ITALIC);
+        }
+        if self.underlined {
+            style = style.add_modifier(Modifier::UNDERLINED);
+        }
+        if self.reversed {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+
+        style
+    }
+}
+
+// user themes only need to set the styles they want to change, the rest comes from the dark theme
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub selection: StyleDef,
+    pub own_message: StyleDef,
+    pub other_message: StyleDef,
+    // "No messages", the new messages divider, loading screens etc.
+    pub system_message: StyleDef,
+    pub unread: StyleDef,
+    pub input_focused: StyleDef,
+    pub borders: StyleDef,
+    // color sender names with the color signal assigned to the contact
+    pub contact_colors: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        Theme {
+            selection: StyleDef::new(None, Some(Color::Blue), false),
+            own_message: StyleDef::default(),
+            other_message: StyleDef::default(),
+            system_message: StyleDef::new(Some(Color::Red), None, false),
+            unread: StyleDef::new(None, None, true),
+            input_focused: StyleDef::new(Some(Color::Blue), None, false),
+            borders: StyleDef::default(),
+            contact_colors: true,
+        }
+    }
+
+    pub fn light() -> Self {
+        Theme {
+            selection: StyleDef::new(Some(Color::Black), Some(Color::LightBlue), false),
+            own_message: StyleDef::new(Some(Color::DarkGray), None, false),
+            other_message: StyleDef::new(Some(Color::Black), None, false),
+            system_message: StyleDef::new(Some(Color::Red), None, false),
+            unread: StyleDef::new(Some(Color::Black), None, true),
+            input_focused: StyleDef::new(Some(Color::Blue), None, false),
+            borders: StyleDef::new(Some(Color::Gray), None, false),
+            contact_colors: true,
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Theme {
+            selection: StyleDef::new(Some(Color::Black), Some(Color::White), true),
+            own_message: StyleDef::new(Some(Color::White), None, false),
+            other_message: StyleDef::new(Some(Color::White), None, false),
+            system_message: StyleDef::new(Some(Color::Yellow), None, true),
+            unread: StyleDef::new(Some(Color::Yellow), None, true),
+            input_focused: StyleDef::new(Some(Color::Yellow), None, true),
+            borders: StyleDef::new(Some(Color::White), None, false),
+            contact_colors: false,
+        }
+    }
+}
+
+// built in themes, or themes/<name>.toml in the config dir
+pub fn load(name: &str) -> color_eyre::Result<()> {
+    let theme = match name {
+        "dark" => Theme::dark(),
+        "light" => Theme::light(),
+        "high-contrast" => Theme::high_contrast(),
+        _ => {
+            let path = crate::config::project_dirs().config_dir().join("themes").join(format!("{}.toml", name));
+
+            if !std::fs::exists(&path)? {
+                return Err(color_eyre::eyre::eyre!(
+                    "Unknown theme \"{}\", expected dark, light, high-contrast or a theme file at {}",
+                    name, path.display()
+                ));
+            }
+
+            let content = std::fs::read_to_string(&path)?;
+            toml::from_str(&content)
+                .map_err(|err| color_eyre::eyre::eyre!("Invalid theme file {}:\n{}", path.display(), err))?
+        }
+    };
+
+    let _ = THEME.set(theme);
+
+    Ok(())
+}
+
+pub fn get() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+// signal-cli gives us the avatar color signal picked for the contact, these are roughly the ones the apps use
+pub fn contact_color(color: &str) -> Option<Color> {
+    let rgb = match color.to_uppercase().as_str() {
+        "ULTRAMARINE" => (0x2c, 0x6b, 0xed),
+        "CRIMSON" => (0xcf, 0x16, 0x3e),
+        "VERMILION" | "VERMILLION" => (0xc7, 0x3f, 0x0a),
+        "BURLAP" => (0x6f, 0x6a, 0x58),
+        "FOREST" => (0x3b, 0x7e, 0x37),
+        "WINTERGREEN" => (0x1d, 0x84, 0x63),
+        "TEAL" => (0x07, 0x7d, 0x92),
+        "BLUE" => (0x33, 0x6b, 0xa3),
+        "INDIGO" => (0x6c, 0x5e, 0xd7),
+        "VIOLET" => (0xa1, 0x4c, 0xa7),
+        "PLUM" => (0xaa, 0x37, 0x7a),
+        "TAUPE" => (0x8f, 0x61, 0x6a),
+        "STEEL" => (0x71, 0x71, 0x7f),
+        _ => return None,
+    };
+
+    Some(Color::Rgb(rgb.0, rgb.1, rgb.2))
+}
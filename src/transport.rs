@@ -0,0 +1,146 @@
+use std::{io::{self, BufRead, BufReader, Read, Write}, path::{Path, PathBuf}, process::Child, sync::mpsc::{self, RecvTimeoutError}, time::Duration};
+
+use color_eyre::{eyre::eyre, Result};
+use serde::Deserialize;
+
+// how we talk json-rpc to signal-cli
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Transport {
+    // start `signal-cli jsonRpc` as a child and use its stdin/stdout
+    #[default]
+    Spawn,
+    // an already running `signal-cli daemon --socket <path>`
+    Socket { path: PathBuf },
+    // an already running `signal-cli daemon --tcp <address>`
+    Tcp { address: String },
+}
+
+impl Transport {
+    // daemons are shared and keep running, so they are never restarted or upgraded by us
+    pub fn is_daemon(&self) -> bool {
+        !matches!(self, Transport::Spawn)
+    }
+}
+
+// requests go through here so they can be tagged with the account signal-cli should use
+pub struct Writer {
+    inner: Box<dyn Write + Send>,
+    pub account: Option<String>,
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// signal-cli's output, read a line at a time on its own thread so waiting for an answer can time out
+pub struct Reader {
+    lines: mpsc::Receiver<String>,
+}
+
+impl Reader {
+    fn new(inner: impl Read + Send + 'static) -> Reader {
+        let (tx, rx) = mpsc::channel();
+
+        // one buffer for the whole connection, daemons often send several lines in one go
+        std::thread::spawn(move || {
+            for line in BufReader::new(inner).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Reader { lines: rx }
+    }
+
+    // the next line, Disconnected once signal-cli is gone. None waits for as long as it takes
+    pub fn read_line(&mut self, timeout: Option<Duration>) -> Result<String, RecvTimeoutError> {
+        match timeout {
+            Some(timeout) => self.lines.recv_timeout(timeout),
+            None => self.lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        }
+    }
+}
+
+// the child we started, if any, so it can be stopped again
+pub struct Process(Option<Child>);
+
+impl Process {
+    pub fn stop(&mut self) {
+        if let Some(child) = &mut self.0 {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        self.0 = None;
+    }
+}
+
+// so early returns dont leave signal-cli running
+impl Drop for Process {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// what a transport hands over before it is wrapped up
+type Streams = (Box<dyn Write + Send>, Box<dyn Read + Send>, Option<Child>);
+
+pub struct Connection {
+    pub writer: Writer,
+    pub reader: Reader,
+    pub process: Process,
+}
+
+// without -a signal-cli serves all accounts, so one connection is enough for every one of them
+pub fn connect() -> Result<Connection> {
+    let transport = &crate::config::get().transport;
+
+    let (writer, reader, child): Streams = match transport {
+        Transport::Spawn => {
+            let mut child = crate::signal::create_cli(vec![])
+                .map_err(|err| eyre!("Could not start {}: {}", crate::config::get().signal_cli_path().display(), err))?;
+
+            let stdin = child.stdin.take().ok_or_else(|| eyre!("signal-cli has no stdin"))?;
+            let stdout = child.stdout.take().ok_or_else(|| eyre!("signal-cli has no stdout"))?;
+
+            (Box::new(stdin), Box::new(stdout), Some(child))
+        }
+        Transport::Socket { path } => connect_socket(path)?,
+        Transport::Tcp { address } => {
+            let stream = std::net::TcpStream::connect(address)
+                .map_err(|err| eyre!("Could not connect to the signal-cli daemon at {}: {}", address, err))?;
+
+            (Box::new(stream.try_clone()?), Box::new(stream), None)
+        }
+    };
+
+    Ok(Connection {
+        writer: Writer { inner: writer, account: None },
+        reader: Reader::new(reader),
+        process: Process(child),
+    })
+}
+
+#[cfg(unix)]
+fn connect_socket(path: &Path) -> Result<Streams> {
+    let stream = std::os::unix::net::UnixStream::connect(path)
+        .map_err(|err| eyre!("Could not connect to the signal-cli daemon at {}: {}", path.display(), err))?;
+
+    Ok((Box::new(stream.try_clone()?), Box::new(stream), None))
+}
+
+#[cfg(not(unix))]
+fn connect_socket(path: &Path) -> Result<Streams> {
+    Err(eyre!("Unix sockets are not supported here, use a tcp transport for {}", path.display()))
+}
@@ -10,20 +10,17 @@ pub struct SignalGenericResponse {
     pub id: Option<String>,
 }
 
-// {"jsonrpc":"2.0","result":{"deviceLinkUri":"sgnl://linkdevice?uuid=X&pub_key=X"},"id":"5"}
+// {"jsonrpc":"2.0","error":{"code":-1,"message":"..."},"id":"5"}
 #[derive(Serialize, Deserialize, Debug)]
-pub struct SignalLinkingResponse {
-    pub jsonrpc: String,
-    pub result: HashMap<String, String>,
-    pub id: String
+pub struct SignalErrorResponse {
+    pub error: SignalError,
+    pub id: Option<String>,
 }
 
-// from { "jsonrpc": "2.0", "method": "listAccounts", "params": {}, "id": "1" }
 #[derive(Serialize, Deserialize, Debug)]
-pub struct SignalAccountList {
-    pub jsonrpc: String,
-    pub result: Vec<SignalAccount>,
-    pub id: String
+pub struct SignalError {
+    pub code: i64,
+    pub message: String,
 }
 
 /*{
@@ -74,8 +71,21 @@ pub struct SignalMessageEvent {
 // not exactly pure from rpc
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct SignalMessageEventParams {
-    pub result: SignalMessageEventResult,
+#[serde(untagged)]
+pub enum SignalMessageEventParams {
+    // subscribeReceive wraps every message in its subscription
+    Subscription { result: SignalMessageEventResult },
+    // daemons send them as they are
+    Direct(SignalMessageEventResult),
+}
+
+impl SignalMessageEventParams {
+    pub fn into_result(self) -> SignalMessageEventResult {
+        match self {
+            SignalMessageEventParams::Subscription { result } => result,
+            SignalMessageEventParams::Direct(result) => result,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -102,6 +112,54 @@ pub struct SignalMessageEventDataMessage {
     pub message: Option<String>,
     pub expires_in_seconds: u64,
     pub group_info: Option<SignalMessageEventGroupInfo>,
+    #[serde(default)]
+    pub mentions: Vec<SignalMention>,
+    pub quote: Option<SignalQuote>,
+    pub reaction: Option<SignalReaction>,
+    #[serde(default)]
+    pub attachments: Vec<SignalAttachment>,
+}
+
+// the message being replied to, id is its timestamp
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SignalQuote {
+    pub id: u64,
+    pub author: Option<String>,
+    pub author_number: Option<String>,
+    pub author_uuid: Option<String>,
+    pub text: Option<String>,
+}
+
+// messages are known by their author and timestamp
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SignalReaction {
+    pub emoji: String,
+    pub target_author_uuid: Option<String>,
+    pub target_sent_timestamp: u64,
+    #[serde(default)]
+    pub is_remove: bool,
+}
+
+// the file itself is in signal-cli's attachments dir under the id
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SignalAttachment {
+    #[serde(default)]
+    pub id: String,
+    pub content_type: Option<String>,
+    pub filename: Option<String>,
+    pub size: Option<u64>,
+    // where the file is if it is not in signal-cli's dir, files we sent or the copy next to an export
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignalMention {
+    pub number: Option<String>,
+    pub uuid: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -117,6 +175,10 @@ pub struct SignalMessageEventSentMessage {
     pub message: Option<String>,
     pub expires_in_seconds: u64,
     pub group_info: Option<SignalMessageEventGroupInfo>,
+    pub quote: Option<SignalQuote>,
+    pub reaction: Option<SignalReaction>,
+    #[serde(default)]
+    pub attachments: Vec<SignalAttachment>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -180,4 +242,117 @@ pub struct SignalUser {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SignalAccount {
     pub number: String,
-}
\ No newline at end of file
+}
+
+// from listDevices, id 1 is the primary device
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SignalDevice {
+    pub id: u64,
+    pub name: Option<String>,
+    pub created_timestamp: u64,
+    pub last_seen_timestamp: u64,
+}
+// -- db --
+
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub source_uuid: String,
+    pub source_name: String,
+    pub source_number: String,
+    pub message: String,
+    pub timestamp: u64,
+}
+
+// the json export of a conversation, version goes up when the layout changes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedConversation {
+    pub version: u32,
+    pub account: String,
+    pub conversation: ArchivedConversationInfo,
+    // when it was exported, in milliseconds
+    #[serde(default)]
+    pub exported: i64,
+    pub messages: Vec<ArchivedMessage>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchivedConversationInfo {
+    pub id: String,
+    // "group" or "contact"
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub name: String,
+}
+
+// a message with everything exports show
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedMessage {
+    pub id: String,
+    pub timestamp: u64,
+    pub source_uuid: String,
+    pub source_number: Option<String>,
+    pub source_name: String,
+    pub message: String,
+    #[serde(default)]
+    pub expires_in_seconds: u64,
+    pub quote: Option<ArchivedQuote>,
+    #[serde(default)]
+    pub reactions: Vec<ArchivedReaction>,
+    #[serde(default)]
+    pub attachments: Vec<SignalAttachment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedQuote {
+    pub timestamp: u64,
+    pub author: Option<String>,
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedReaction {
+    pub emoji: String,
+    pub source_uuid: String,
+    pub source_name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConversationStats {
+    pub last_message: u64,
+    pub unread: usize,
+}
+
+// -- events --
+
+// everything the event reader passes on to the ui
+#[derive(Debug, Clone)]
+pub enum ReaderEvent {
+    Message(IncomingMessage),
+    // a request we sent failed, id is the one returned by signal::send_request
+    Error { id: Option<String>, message: String },
+    // the answer to any other request, sends are handled in the reader
+    Response { id: String, result: serde_json::Value },
+    // something was written to the database that has no message to show for it, like a reaction or a sent receipt
+    Changed,
+    // nothing more will come, why the reader stopped
+    Closed(String),
+}
+
+// sent from the event reader to the ui for every new message from someone else
+#[derive(Debug, Clone)]
+pub struct IncomingMessage {
+    pub account_number: String,
+    // group id or the senders uuid
+    pub conversation_id: String,
+    pub group_id: Option<String>,
+    pub source_number: Option<String>,
+    pub source_name: String,
+    pub timestamp: u64,
+    pub message: String,
+    pub mentions: Vec<SignalMention>,
+}
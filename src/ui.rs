@@ -0,0 +1,146 @@
+use color_eyre::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use qrcode::QrCode;
+use ratatui::{
+    layout::{Alignment, Constraint, Direction, Layout, Position, Rect},
+    widgets::{Block, BorderType, Borders, Paragraph, Wrap},
+    DefaultTerminal, Frame
+};
+
+// the smallest terminal the main screen is drawn in
+pub const MIN_WIDTH: u16 = 30;
+pub const MIN_HEIGHT: u16 = 8;
+
+// centered in area, and never bigger than it so small terminals cant draw outside the buffer
+pub fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height
+    )
+}
+
+// draws a notice instead of the ui when the terminal is below the minimum size, returns true if it did
+pub fn too_small(f: &mut Frame, min_width: u16, min_height: u16) -> bool {
+    let area = f.area();
+
+    if area.width >= min_width && area.height >= min_height {
+        return false;
+    }
+
+    let text = format!(
+        "Terminal too small\n{}x{}, need {}x{}",
+        area.width, area.height, min_width, min_height
+    );
+
+    let rect = centered_rect(area, area.width, 2);
+    f.render_widget(
+        Paragraph::new(text)
+            .style(crate::theme::get().system_message.style())
+            .alignment(Alignment::Center)
+            .wrap(Wrap { trim: true }),
+        rect
+    );
+
+    true
+}
+
+// the qr code in half blocks, two rows of modules per line of text
+pub fn qr_text(data: &str) -> String {
+    let code = QrCode::new(data).unwrap();
+
+    let qr = code.render::<image::Luma<u8>>()
+        .quiet_zone(true)
+        .module_dimensions(1,1)
+        .build();
+
+    let mut out = String::new();
+    let width = qr.width() as usize;
+    let height = qr.height() as usize;
+
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = qr.get_pixel(x as u32, y as u32)[0] < 128;
+            let bottom = if y + 1 < height {
+                qr.get_pixel(x as u32, (y + 1) as u32)[0] < 128
+            } else {
+                false
+            };
+            let ch = match (top, bottom) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            };
+            out.push(ch);
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+// a one line text field under a message, None if it was left with esc
+pub fn prompt(terminal: &mut DefaultTerminal, title: &str, message: &str, secret: bool) -> Result<Option<String>> {
+    let mut text = String::new();
+
+    loop {
+        terminal.draw(|f| {
+            let width = (message.lines().map(|line| line.chars().count()).max().unwrap_or(0).max(40) as u16 + 4).min(80);
+            let height = message.lines().count() as u16 + 6;
+            let area = centered_rect(f.area(), width, height);
+
+            let block = Block::default()
+                .title(format!(" {} ", title))
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(crate::theme::get().borders.style());
+            let inner = block.inner(area);
+
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Fill(1), Constraint::Length(3)])
+                .split(inner);
+
+            f.render_widget(block, area);
+            f.render_widget(
+                Paragraph::new(message).alignment(Alignment::Center).wrap(Wrap { trim: false }),
+                chunks[0]
+            );
+
+            // long input like captcha tokens only shows its end
+            let shown: Vec<char> = if secret {
+                vec!['*'; text.chars().count()]
+            } else {
+                text.chars().collect()
+            };
+            let room = chunks[1].width.saturating_sub(3) as usize;
+            let visible: String = shown[shown.len().saturating_sub(room)..].iter().collect();
+
+            f.render_widget(Paragraph::new(visible.as_str()).block(Block::bordered()), chunks[1]);
+            f.set_cursor_position(Position::new(
+                chunks[1].x + 1 + visible.chars().count() as u16,
+                chunks[1].y + 1
+            ));
+        })?;
+
+        match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Enter => return Ok(Some(text.trim().to_string())),
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) => text.push(c),
+                _ => {}
+            },
+            Event::Paste(pasted) => text.push_str(pasted.trim()),
+            _ => {}
+        }
+    }
+}